@@ -0,0 +1,334 @@
+//! Vault audit. Every record is opened and its password checked for weakness, shortness and reuse
+//! across the vault, along with how long ago it was last changed. Passwords can also be looked up
+//! in a local dump of breached passwords.
+
+use crate::{
+    breach::HashDump,
+    commands::{Executable, VaultManager},
+    display::{format_rfc3339, format_timestamp, print_json, OutputFormat},
+    oneshot::VaultArgs,
+    strength::{self, Strength},
+};
+use anyhow::Result;
+use protobuf::{well_known_types::timestamp::Timestamp, MessageField};
+use serde_json::{json, Value};
+use std::path::Path;
+
+/// Passwords shorter than this are reported, whatever their estimated strength
+pub const MIN_LENGTH: usize = 8;
+/// Days after which a password that was not changed is reported
+pub const DEFAULT_MAX_AGE: u32 = 365;
+
+/// Password of a record, along with where it is stored and when it was last changed
+struct AuditedRecord {
+    path: String,
+    password: String,
+    modified_at: Option<Timestamp>,
+}
+
+#[derive(Debug, Default)]
+pub struct AuditReport {
+    pub weak: Vec<(String, Strength)>,
+    pub short: Vec<String>,
+    /// Groups of records sharing a password
+    pub reused: Vec<Vec<String>>,
+    pub stale: Vec<(String, Timestamp)>,
+    /// Records whose password appears in the breach dump, with the number of times it was seen
+    pub breached: Vec<(String, u64)>,
+}
+
+impl AuditReport {
+    /// Audits every record of `vm`. Passwords older than `max_age` days are reported as stale,
+    /// unless it is 0, and passwords are looked up in the breach dump at `breached` if given.
+    /// Records without a password are skipped.
+    pub fn new(vm: &mut VaultManager, max_age: u32, breached: Option<&Path>) -> Result<Self> {
+        let mut dump = breached.map(HashDump::open).transpose()?;
+        let mut records = Vec::new();
+        collect_records(vm, "", &mut records)?;
+        Self::from_records(records, max_age, dump.as_mut())
+    }
+
+    fn from_records(
+        mut records: Vec<AuditedRecord>,
+        max_age: u32,
+        mut dump: Option<&mut HashDump>,
+    ) -> Result<Self> {
+        records.retain(|record| !record.password.is_empty());
+        let mut report = Self::default();
+        let cutoff = Timestamp::now().seconds - i64::from(max_age) * 86400;
+        for (index, record) in records.iter().enumerate() {
+            let strength = strength::estimate(&record.password);
+            if strength.is_weak() {
+                report.weak.push((record.path.clone(), strength));
+            }
+            if record.password.chars().count() < MIN_LENGTH {
+                report.short.push(record.path.clone());
+            }
+            if let Some(dump) = dump.as_deref_mut() {
+                if let Some(count) = dump.count(&record.password)? {
+                    report.breached.push((record.path.clone(), count));
+                }
+            }
+            if let Some(modified_at) = &record.modified_at {
+                if max_age > 0 && modified_at.seconds < cutoff {
+                    report
+                        .stale
+                        .push((record.path.clone(), modified_at.clone()));
+                }
+            }
+
+            // Each group is reported once, from the first record sharing the password.
+            if records[..index]
+                .iter()
+                .any(|other| other.password == record.password)
+            {
+                continue;
+            }
+            let group: Vec<String> = records[index..]
+                .iter()
+                .filter(|other| other.password == record.password)
+                .map(|other| other.path.clone())
+                .collect();
+            if group.len() > 1 {
+                report.reused.push(group);
+            }
+        }
+        Ok(report)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.weak.is_empty()
+            && self.short.is_empty()
+            && self.reused.is_empty()
+            && self.stale.is_empty()
+            && self.breached.is_empty()
+    }
+
+    /// JSON document of the report, with every kind of issue listed even when there is none.
+    pub fn to_json(&self) -> Value {
+        let weak: Vec<Value> = self
+            .weak
+            .iter()
+            .map(|(path, strength)| {
+                json!({
+                    "key": path,
+                    "score": strength.score,
+                    "log_guesses": strength.log_guesses,
+                })
+            })
+            .collect();
+        let stale: Vec<Value> = self
+            .stale
+            .iter()
+            .map(|(path, modified_at)| {
+                json!({
+                    "key": path,
+                    "modified": format_rfc3339(&MessageField::some(modified_at.clone())),
+                })
+            })
+            .collect();
+        let breached: Vec<Value> = self
+            .breached
+            .iter()
+            .map(|(path, count)| json!({ "key": path, "count": count }))
+            .collect();
+        json!({
+            "weak": weak,
+            "short": self.short,
+            "reused": self.reused,
+            "stale": stale,
+            "breached": breached,
+        })
+    }
+
+    pub fn print(&self, max_age: u32) {
+        if self.is_empty() {
+            println!("No issues found");
+            return;
+        }
+        if !self.breached.is_empty() {
+            println!("Passwords found in breaches:");
+            for (path, count) in &self.breached {
+                println!("  {:<32} seen {} times", path, count);
+            }
+        }
+        if !self.weak.is_empty() {
+            println!("Weak passwords:");
+            for (path, strength) in &self.weak {
+                println!(
+                    "  {:<32} {}, about 10^{:.0} guesses",
+                    path,
+                    strength.description(),
+                    strength.log_guesses
+                );
+            }
+        }
+        if !self.short.is_empty() {
+            println!("Passwords shorter than {} characters:", MIN_LENGTH);
+            for path in &self.short {
+                println!("  {}", path);
+            }
+        }
+        if !self.reused.is_empty() {
+            println!("Reused passwords:");
+            for group in &self.reused {
+                println!("  {}", group.join(", "));
+            }
+        }
+        if !self.stale.is_empty() {
+            println!("Passwords not changed in {} days:", max_age);
+            for (path, modified_at) in &self.stale {
+                println!(
+                    "  {:<32} last changed {}",
+                    path,
+                    format_timestamp(modified_at)
+                );
+            }
+        }
+    }
+}
+
+/// Opens every record under the directory at `path`, depth first.
+fn collect_records(
+    vm: &mut VaultManager,
+    path: &str,
+    records: &mut Vec<AuditedRecord>,
+) -> Result<()> {
+    let dm = vm.open_dir(path)?;
+    let names: Vec<String> = dm
+        .get_record_names()
+        .into_iter()
+        .map(String::from)
+        .collect();
+    for name in names {
+        records.push(AuditedRecord {
+            path: format!("{}/{}", path, name),
+            password: dm.read_entry(&name)?.password,
+            modified_at: dm.record(&name)?.modified_at.clone().into_option(),
+        });
+    }
+    let directories: Vec<String> = dm
+        .get_directory_names()
+        .into_iter()
+        .map(String::from)
+        .collect();
+    for directory in directories {
+        collect_records(vm, &format!("{}/{}", path, directory), records)?;
+    }
+    Ok(())
+}
+
+pub struct AuditCommand {
+    vault: VaultArgs,
+    max_age: u32,
+    breached: Option<String>,
+    format: OutputFormat,
+}
+
+impl AuditCommand {
+    pub fn new(
+        vault: VaultArgs,
+        max_age: u32,
+        breached: Option<String>,
+        format: OutputFormat,
+    ) -> Self {
+        Self {
+            vault,
+            max_age,
+            breached,
+            format,
+        }
+    }
+}
+
+/// Audits a vault without locking or saving it.
+impl Executable for AuditCommand {
+    fn execute(&self) -> Result<()> {
+        let mut vm = self.vault.open()?;
+        let report = AuditReport::new(
+            &mut vm,
+            self.max_age,
+            self.breached.as_deref().map(Path::new),
+        )?;
+        match self.format {
+            OutputFormat::Text => report.print(self.max_age),
+            OutputFormat::Json => print_json(&report.to_json()),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    #[test]
+    fn test_audit() {
+        let mut vm = VaultManager::default();
+        vm.regenerate(String::from("abcdefgh")).unwrap();
+        vm.add_directory("email").unwrap();
+        vm.add_directory("email/old").unwrap();
+        vm.open_dir("")
+            .unwrap()
+            .add_record("bank", "password")
+            .unwrap();
+        let mut dm = vm.open_dir("email").unwrap();
+        dm.add_record("gmail", "Xk9#mQ2!vL7p-zebra").unwrap();
+        dm.add_record("work", "Xk9#mQ2!vL7p-zebra").unwrap();
+        dm.add_record("empty", "").unwrap();
+        let mut dm = vm.open_dir("email/old").unwrap();
+        dm.add_record("yahoo", "tR4$").unwrap();
+
+        let report = AuditReport::new(&mut vm, 0, None).unwrap();
+        let weak: Vec<&str> = report.weak.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(weak, vec!["/bank", "/email/old/yahoo"]);
+        assert_eq!(report.short, vec!["/email/old/yahoo"]);
+        assert_eq!(report.reused, vec![vec!["/email/gmail", "/email/work"]]);
+        assert!(report.stale.is_empty());
+        assert!(report.breached.is_empty());
+        let dm = vm.open_dir("").unwrap();
+        assert!(dm.record("bank").unwrap().accessed_at.is_none());
+
+        // SHA-1 of "password"
+        let dump = env::temp_dir().join(format!("rustpass-audit-hibp-{}.txt", process::id()));
+        fs::write(&dump, "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:42\n").unwrap();
+        let report = AuditReport::new(&mut vm, 0, Some(&dump)).unwrap();
+        assert_eq!(report.breached, vec![(String::from("/bank"), 42)]);
+        let document = report.to_json();
+        assert_eq!(
+            document["breached"],
+            json!([{ "key": "/bank", "count": 42 }])
+        );
+        assert_eq!(document["short"], json!(["/email/old/yahoo"]));
+        assert_eq!(document["weak"][0]["key"], "/bank");
+        assert!(!AuditReport {
+            breached: report.breached,
+            ..Default::default()
+        }
+        .is_empty());
+        fs::remove_file(&dump).unwrap();
+
+        let modified_at = |days: i64| {
+            let mut timestamp = Timestamp::now();
+            timestamp.seconds -= days * 86400;
+            Some(timestamp)
+        };
+        let records = vec![
+            AuditedRecord {
+                path: String::from("/old"),
+                password: String::from("Xk9#mQ2!vL7p-zebra"),
+                modified_at: modified_at(2 * 365),
+            },
+            AuditedRecord {
+                path: String::from("/new"),
+                password: String::from("Xk9#mQ2!vL7p-quilt"),
+                modified_at: modified_at(30),
+            },
+        ];
+        let report = AuditReport::from_records(records, DEFAULT_MAX_AGE, None).unwrap();
+        let stale: Vec<&str> = report.stale.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(stale, vec!["/old"]);
+        assert!(report.weak.is_empty() && report.reused.is_empty());
+    }
+}
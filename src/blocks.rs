@@ -0,0 +1,255 @@
+//! Framing of a `.rpdb` file: the header, its HMAC and the block stream holding the sealed body.
+//!
+//! Every block is written as `HMAC ‖ length ‖ data`. The HMAC of block `i` covers
+//! `i ‖ length ‖ data` and uses a key derived from the block index, so blocks cannot be
+//! reordered or dropped without being detected. An empty block ends the stream, which makes a
+//! truncated file distinguishable from a complete one.
+
+use std::io::{ErrorKind, Read, Write};
+
+use anyhow::Result;
+use ring::{
+    digest::{digest, SHA256_OUTPUT_LEN, SHA512, SHA512_OUTPUT_LEN},
+    hmac::{self, HMAC_SHA256},
+};
+
+use crate::error::RustPassError;
+
+/// First four bytes of every file in the block format. Files written before version 0x0004 were
+/// a bare protobuf and never start with these bytes.
+pub const SIGNATURE: u32 = 0x3A7F9C42;
+
+const HEADER_INDEX: u64 = u64::MAX;
+const MAX_HEADER_LEN: usize = 1 << 20;
+pub const MAX_BLOCK_LEN: usize = 1 << 26;
+
+type MacBuffer = [u8; SHA256_OUTPUT_LEN];
+
+/// Source of the HMAC keys for the header and for each block.
+pub struct BlockKeys {
+    base: [u8; SHA512_OUTPUT_LEN],
+}
+
+impl BlockKeys {
+    /// Derives `SHA-512(S ‖ T ‖ 0x01)` from the master salt `S` and the master hash `T`.
+    pub fn new(master_salt: &[u8], master_hash: &[u8]) -> Self {
+        let mut buf = master_salt.to_vec();
+        buf.extend_from_slice(master_hash);
+        buf.push(0x01);
+        let mut base = [0; SHA512_OUTPUT_LEN];
+        base.copy_from_slice(digest(&SHA512, &buf).as_ref());
+        buf.fill(0);
+        Self { base }
+    }
+
+    /// Key for block `index`: `SHA-512(index ‖ base)`. The header uses `u64::MAX` as its index.
+    fn key(&self, index: u64) -> hmac::Key {
+        let mut buf = index.to_le_bytes().to_vec();
+        buf.extend_from_slice(&self.base);
+        let key = hmac::Key::new(HMAC_SHA256, digest(&SHA512, &buf).as_ref());
+        buf.fill(0);
+        key
+    }
+}
+
+impl Drop for BlockKeys {
+    fn drop(&mut self) {
+        self.base.fill(0);
+    }
+}
+
+/// Data authenticated by the HMAC of a block: `index ‖ length ‖ data`.
+fn block_message(index: u64, data: &[u8]) -> Vec<u8> {
+    let mut message = index.to_le_bytes().to_vec();
+    message.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    message.extend_from_slice(data);
+    message
+}
+
+/// Writes the signature, the length prefixed header and its HMAC.
+pub fn write_header<W: Write>(writer: &mut W, header: &[u8], keys: &BlockKeys) -> Result<()> {
+    writer.write_all(&SIGNATURE.to_le_bytes())?;
+    writer.write_all(&(header.len() as u32).to_le_bytes())?;
+    writer.write_all(header)?;
+    writer.write_all(hmac::sign(&keys.key(HEADER_INDEX), header).as_ref())?;
+    Ok(())
+}
+
+/// Reads the header that follows the signature, along with its HMAC. The HMAC can only be checked
+/// with [`verify_header`] once the keys have been derived from the header contents.
+pub fn read_header<R: Read>(reader: &mut R) -> Result<(Vec<u8>, MacBuffer)> {
+    let len = read_u32(reader).map_err(|_| {
+        RustPassError::Corrupted(String::from("Vault is truncated inside the header"))
+    })?;
+    if len as usize > MAX_HEADER_LEN {
+        return Err(RustPassError::Corrupted(String::from("Vault header is corrupted")).into());
+    }
+    let mut header = vec![0; len as usize];
+    let mut mac = MacBuffer::default();
+    reader
+        .read_exact(&mut header)
+        .and_then(|_| reader.read_exact(&mut mac))
+        .map_err(|_| {
+            RustPassError::Corrupted(String::from("Vault is truncated inside the header"))
+        })?;
+    Ok((header, mac))
+}
+
+pub fn verify_header(header: &[u8], mac: &MacBuffer, keys: &BlockKeys) -> Result<()> {
+    hmac::verify(&keys.key(HEADER_INDEX), header, mac)
+        .map_err(|_| RustPassError::Corrupted(String::from("Vault header is corrupted")).into())
+}
+
+/// Splits `data` into blocks of at most `chunk_size` bytes, followed by the closing empty block.
+pub fn write_blocks<W: Write>(
+    writer: &mut W,
+    data: &[u8],
+    chunk_size: usize,
+    keys: &BlockKeys,
+) -> Result<()> {
+    if chunk_size == 0 || chunk_size > MAX_BLOCK_LEN {
+        return Err(RustPassError::InvalidInput(String::from("Invalid chunk size")).into());
+    }
+    let closing: &[u8] = &[];
+    for (index, chunk) in data.chunks(chunk_size).chain([closing]).enumerate() {
+        let index = index as u64;
+        writer.write_all(hmac::sign(&keys.key(index), &block_message(index, chunk)).as_ref())?;
+        writer.write_all(&(chunk.len() as u32).to_le_bytes())?;
+        writer.write_all(chunk)?;
+    }
+    Ok(())
+}
+
+/// Reads and authenticates blocks until the closing empty block, returning their concatenated
+/// contents. Errors name the first block that is missing or fails verification.
+pub fn read_blocks<R: Read>(reader: &mut R, keys: &BlockKeys) -> Result<Vec<u8>> {
+    let mut data = vec![];
+    for index in 0u64.. {
+        let truncated =
+            || RustPassError::Corrupted(format!("Vault is truncated at block {}", index));
+        let mut mac = MacBuffer::default();
+        reader.read_exact(&mut mac).map_err(|_| truncated())?;
+        let len = read_u32(reader).map_err(|_| truncated())? as usize;
+        if len > MAX_BLOCK_LEN {
+            return Err(RustPassError::Corrupted(format!("Block {} is corrupted", index)).into());
+        }
+        // The length is not authenticated yet, so the block grows with the bytes actually read
+        // rather than being allocated upfront, and is only kept once its MAC is verified.
+        let mut block = vec![];
+        reader
+            .by_ref()
+            .take(len as u64)
+            .read_to_end(&mut block)
+            .map_err(|_| truncated())?;
+        if block.len() != len {
+            return Err(truncated().into());
+        }
+
+        hmac::verify(&keys.key(index), &block_message(index, &block), &mac)
+            .map_err(|_| RustPassError::Corrupted(format!("Block {} is corrupted", index)))?;
+        data.extend_from_slice(&block);
+        if len == 0 {
+            break;
+        }
+    }
+
+    match reader.read(&mut [0]) {
+        Ok(0) => Ok(data),
+        Err(e) if e.kind() != ErrorKind::Interrupted => Err(e.into()),
+        _ => Err(
+            RustPassError::Corrupted(String::from("Unexpected data after the last block")).into(),
+        ),
+    }
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> std::io::Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> BlockKeys {
+        BlockKeys::new(&[1; 32], &[2; 32])
+    }
+
+    fn write(data: &[u8], chunk_size: usize) -> Vec<u8> {
+        let mut buf = vec![];
+        write_header(&mut buf, b"header", &keys()).unwrap();
+        write_blocks(&mut buf, data, chunk_size, &keys()).unwrap();
+        buf
+    }
+
+    fn read(mut buf: &[u8]) -> Result<Vec<u8>> {
+        let mut signature = [0; 4];
+        buf.read_exact(&mut signature)?;
+        assert_eq!(u32::from_le_bytes(signature), SIGNATURE);
+        let (header, mac) = read_header(&mut buf)?;
+        verify_header(&header, &mac, &keys())?;
+        read_blocks(&mut buf, &keys())
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let data: Vec<u8> = (0..100).collect();
+        for chunk_size in [1, 7, 100, 1000] {
+            assert_eq!(read(&write(&data, chunk_size)).unwrap(), data);
+        }
+        assert!(read(&write(&[], 16)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_wrong_key() {
+        let buf = write(b"abc", 16);
+        let mut reader = &buf[4..];
+        let (header, mac) = read_header(&mut reader).unwrap();
+        assert!(verify_header(&header, &mac, &BlockKeys::new(&[1; 32], &[3; 32])).is_err());
+    }
+
+    #[test]
+    fn test_corruption_is_located() {
+        let data = [0u8; 40];
+        let buf = write(&data, 10);
+        // signature, header length, header, header MAC, then blocks of MAC, length and data
+        let block_start = |index: usize| 4 + 4 + 6 + 32 + index * (32 + 4 + 10);
+
+        let mut corrupted = buf.clone();
+        corrupted[block_start(2) + 32 + 4 + 3] ^= 1;
+        let err = read(&corrupted).unwrap_err().to_string();
+        assert_eq!(err, "Block 2 is corrupted");
+
+        let mut swapped = buf.clone();
+        let (first, second) = (
+            block_start(0)..block_start(1),
+            block_start(1)..block_start(2),
+        );
+        let block = swapped[first.clone()].to_vec();
+        swapped.copy_within(second, first.start);
+        swapped[block_start(1)..block_start(2)].copy_from_slice(&block);
+        let err = read(&swapped).unwrap_err().to_string();
+        assert_eq!(err, "Block 0 is corrupted");
+
+        let err = read(&buf[..block_start(3) + 5]).unwrap_err().to_string();
+        assert_eq!(err, "Vault is truncated at block 3");
+        let err = read(&buf[..block_start(4)]).unwrap_err().to_string();
+        assert_eq!(err, "Vault is truncated at block 4");
+
+        let mut extended = buf.clone();
+        extended.push(0);
+        assert!(read(&extended).is_err());
+    }
+
+    #[test]
+    fn test_unauthenticated_length_is_not_trusted() {
+        let buf = write(&[0u8; 10], 10);
+        let length_start = 4 + 4 + 6 + 32 + 32;
+        let mut crafted = buf[..length_start].to_vec();
+        crafted.extend_from_slice(&(MAX_BLOCK_LEN as u32).to_le_bytes());
+        crafted.extend_from_slice(&[0; 10]);
+        let err = read(&crafted).unwrap_err().to_string();
+        assert_eq!(err, "Vault is truncated at block 0");
+    }
+}
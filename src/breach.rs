@@ -0,0 +1,176 @@
+//! Offline lookups in a Have I Been Pwned password dump. Nothing is ever sent over the network:
+//! the SHA-1 hash of a password is binary searched in files downloaded beforehand.
+//!
+//! Two layouts are supported, both with a `HASH:COUNT` line per breached password, sorted by hash:
+//! - a single file of full hashes, such as `pwned-passwords-sha1-ordered-by-hash-v8.txt`
+//! - a directory of range files, named after the first 5 hex digits of the hashes they hold and
+//!   listing the remaining 35, as served by the range API and saved by the official downloader
+
+use crate::error::RustPassError;
+use anyhow::Result;
+use ring::digest::{digest, SHA1_FOR_LEGACY_USE_ONLY};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+/// Length of the hash prefix range files are named after
+const RANGE_PREFIX_LEN: usize = 5;
+/// Size under which the remaining part of a file is scanned line by line
+const SCAN_SIZE: u64 = 4096;
+
+pub enum HashDump {
+    Sorted(BufReader<File>),
+    Ranges(PathBuf),
+}
+
+impl HashDump {
+    pub fn open(path: &Path) -> Result<Self> {
+        if path.is_dir() {
+            return Ok(Self::Ranges(path.to_path_buf()));
+        }
+        let file = File::open(path).map_err(|source| RustPassError::Io {
+            context: format!("Could not open hash file {}", path.display()),
+            source,
+        })?;
+        Ok(Self::Sorted(BufReader::new(file)))
+    }
+
+    /// Number of times `password` appears in breaches, or `None` if it is not in the dump.
+    pub fn count(&mut self, password: &str) -> Result<Option<u64>> {
+        let hash: String = digest(&SHA1_FOR_LEGACY_USE_ONLY, password.as_bytes())
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        match self {
+            Self::Sorted(reader) => search(reader, &hash),
+            Self::Ranges(dir) => {
+                let (prefix, suffix) = hash.split_at(RANGE_PREFIX_LEN);
+                let Some(path) = [prefix.to_string(), format!("{}.txt", prefix)]
+                    .iter()
+                    .map(|name| dir.join(name))
+                    .find(|path| path.is_file())
+                else {
+                    return Err(RustPassError::NotFound(format!(
+                        "Range file for {} in {}",
+                        prefix,
+                        dir.display()
+                    ))
+                    .into());
+                };
+                search(&mut BufReader::new(File::open(path)?), suffix)
+            }
+        }
+    }
+}
+
+/// Binary searches the sorted `HASH:COUNT` lines of `reader` for `target`, by byte offset.
+fn search<R: BufRead + Seek>(reader: &mut R, target: &str) -> Result<Option<u64>> {
+    // The line holding `target`, if any, lies between the first lines starting at `lo` and at
+    // `hi`.
+    let mut lo = 0;
+    let mut hi = reader.seek(SeekFrom::End(0))?;
+    while hi - lo > SCAN_SIZE {
+        let mid = lo + (hi - lo) / 2;
+        match line_at(reader, mid)? {
+            Some(line) if hash_of(&line).as_str() < target => lo = mid,
+            _ => hi = mid,
+        }
+    }
+
+    let mut line = line_at(reader, lo)?;
+    while let Some(current) = line {
+        let hash = hash_of(&current);
+        if hash.as_str() > target {
+            break;
+        }
+        if hash == target {
+            let count = current
+                .split(':')
+                .nth(1)
+                .and_then(|count| count.trim().parse().ok())
+                .ok_or(RustPassError::Corrupted(format!(
+                    "Malformed line in hash file: {}",
+                    current.trim()
+                )))?;
+            return Ok(Some(count));
+        }
+        line = next_line(reader)?;
+    }
+    Ok(None)
+}
+
+/// Reads the first line starting at or after `offset`.
+fn line_at<R: BufRead + Seek>(reader: &mut R, offset: u64) -> Result<Option<String>> {
+    if offset == 0 {
+        reader.seek(SeekFrom::Start(0))?;
+    } else {
+        // Skip the rest of the line `offset` falls in, unless it is right after a line break.
+        reader.seek(SeekFrom::Start(offset - 1))?;
+        reader.read_line(&mut String::new())?;
+    }
+    next_line(reader)
+}
+
+fn next_line<R: BufRead>(reader: &mut R) -> Result<Option<String>> {
+    let mut line = String::new();
+    Ok(match reader.read_line(&mut line)? {
+        0 => None,
+        _ => Some(line),
+    })
+}
+
+fn hash_of(line: &str) -> String {
+    line.split(':')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_uppercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, io::Cursor, process};
+
+    #[test]
+    fn test_search() {
+        let lines: Vec<String> = (0..2000u32)
+            .map(|i| format!("{:040X}:{}\r\n", i * 2, i + 1))
+            .collect();
+        let mut reader = Cursor::new(lines.concat().into_bytes());
+        for i in [0u32, 1, 17, 1000, 1999] {
+            let found = search(&mut reader, &format!("{:040X}", i * 2)).unwrap();
+            assert_eq!(found, Some(u64::from(i) + 1));
+            let missing = search(&mut reader, &format!("{:040X}", i * 2 + 1)).unwrap();
+            assert_eq!(missing, None);
+        }
+        assert_eq!(search(&mut Cursor::new(Vec::new()), "00").unwrap(), None);
+    }
+
+    #[test]
+    fn test_dump() {
+        // SHA-1 of "password"
+        let hash = "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8";
+        let file = env::temp_dir().join(format!("rustpass-hibp-{}.txt", process::id()));
+        fs::write(
+            &file,
+            format!("0000000000000000000000000000000000000000:1\n{}:42\n", hash),
+        )
+        .unwrap();
+        let mut dump = HashDump::open(&file).unwrap();
+        assert_eq!(dump.count("password").unwrap(), Some(42));
+        assert_eq!(dump.count("Xk9#mQ2!vL7p-zebra").unwrap(), None);
+        fs::remove_file(&file).unwrap();
+
+        let dir = env::temp_dir().join(format!("rustpass-hibp-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("5BAA6.txt"), format!("{}:7\n", &hash[5..])).unwrap();
+        let mut dump = HashDump::open(&dir).unwrap();
+        assert_eq!(dump.count("password").unwrap(), Some(7));
+        assert!(dump.count("Xk9#mQ2!vL7p-zebra").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,14 +1,13 @@
-use std::borrow::BorrowMut;
-use std::fs::{create_dir_all, File};
-use std::io::{Read, Write};
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
 use std::path::Path;
 
-use anyhow::{anyhow, Ok, Result};
-use argon2::Argon2;
+use anyhow::{Ok, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
 use protobuf::{well_known_types::timestamp::Timestamp, Message, MessageField};
 use rand::distr::{Distribution, Uniform};
-use rand::rngs::StdRng;
-use rand::SeedableRng;
+use rand::rngs::OsRng;
+use rand::TryRngCore;
 use ring::{
     aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
     digest::SHA256_OUTPUT_LEN,
@@ -16,13 +15,26 @@ use ring::{
     rand::{SecureRandom, SystemRandom},
 };
 
-use crate::protos::rpdb::Record;
+use crate::protos::rpdb::{trash_item::Item, Entry, Record, RecordVersion, TrashItem};
 use crate::{
-    config::ConfigCommand,
+    audit::AuditCommand,
+    blocks::{self, BlockKeys, SIGNATURE},
+    config::{ConfigCommand, LocalConfig},
     create::CreateCommand,
+    display::OutputFormat,
+    entry::EntryField,
+    error::RustPassError,
+    generate::{capitalize, GenerateCommand, PassphraseCommand, PassphrasePolicy, PasswordPolicy},
+    keyfile::MasterKey,
+    oneshot::{AddCommand, GetCommand, LsCommand, RmCommand, ShowCommand, VaultArgs},
     open::OpenCommand,
     parsing::Commands,
-    protos::rpdb::{Body, Directory, Header, RPDB},
+    passwd::PasswdCommand,
+    password::PasswordSource,
+    protos::rpdb::{
+        Body, Directory, Header, KdfAlgorithm, KdfParams, KeySlot, PasswordEncoding, RPDB,
+    },
+    slots, storage,
 };
 
 pub trait Executable {
@@ -32,11 +44,128 @@ pub trait Executable {
     }
 }
 
-pub fn command_factory(command: Commands) -> Box<dyn Executable> {
+pub fn command_factory(command: Commands, format: OutputFormat) -> Box<dyn Executable> {
     match command {
-        Commands::Create { name, dir } => Box::new(CreateCommand::new(name, dir)),
-        Commands::Open { file_path } => Box::new(OpenCommand::new(file_path)),
-        Commands::Config => Box::new(ConfigCommand::new()),
+        Commands::Create {
+            name,
+            dir,
+            keyfile,
+            password,
+        } => Box::new(CreateCommand::new(
+            name,
+            dir,
+            keyfile,
+            PasswordSource::from(&password),
+        )),
+        Commands::Open {
+            file_path,
+            read_only,
+            keyfile,
+            password,
+        } => Box::new(OpenCommand::new(
+            file_path,
+            read_only,
+            keyfile,
+            PasswordSource::from(&password),
+        )),
+        Commands::Get {
+            file_path,
+            key_path,
+            field,
+            keyfile,
+            password,
+        } => Box::new(GetCommand::new(
+            VaultArgs::new(file_path, keyfile, PasswordSource::from(&password)),
+            key_path,
+            field,
+            format,
+        )),
+        Commands::Show {
+            file_path,
+            key_path,
+            reveal,
+            keyfile,
+            password,
+        } => Box::new(ShowCommand::new(
+            VaultArgs::new(file_path, keyfile, PasswordSource::from(&password)),
+            key_path,
+            reveal,
+            format,
+        )),
+        Commands::Add {
+            file_path,
+            key_path,
+            keyfile,
+            password,
+        } => Box::new(AddCommand::new(
+            VaultArgs::new(file_path, keyfile, PasswordSource::from(&password)),
+            key_path,
+        )),
+        Commands::Ls {
+            file_path,
+            path,
+            keyfile,
+            password,
+        } => Box::new(LsCommand::new(
+            VaultArgs::new(file_path, keyfile, PasswordSource::from(&password)),
+            path,
+            format,
+        )),
+        Commands::Rm {
+            file_path,
+            key_path,
+            keyfile,
+            password,
+        } => Box::new(RmCommand::new(
+            VaultArgs::new(file_path, keyfile, PasswordSource::from(&password)),
+            key_path,
+        )),
+        Commands::Passwd {
+            file_path,
+            keyfile,
+            new_keyfile,
+            remove_keyfile,
+            password,
+        } => Box::new(PasswdCommand::new(
+            file_path,
+            keyfile,
+            new_keyfile,
+            remove_keyfile,
+            PasswordSource::from(&password),
+        )),
+        Commands::Config {
+            chunk_size,
+            iterations,
+            memory,
+            parallelism,
+            backups,
+            history,
+            trash_retention,
+            unicode_normalization,
+        } => Box::new(ConfigCommand::new(
+            chunk_size,
+            iterations,
+            memory,
+            parallelism,
+            backups,
+            history,
+            trash_retention,
+            unicode_normalization,
+        )),
+        Commands::Audit {
+            file_path,
+            keyfile,
+            max_age,
+            breached,
+            password,
+        } => Box::new(AuditCommand::new(
+            VaultArgs::new(file_path, keyfile, PasswordSource::from(&password)),
+            max_age,
+            breached,
+            format,
+        )),
+        Commands::Generate { args } => Box::new(GenerateCommand::new(args)),
+        Commands::Passphrase { args } => Box::new(PassphraseCommand::new(args)),
     }
 }
 
@@ -48,78 +177,126 @@ pub type SaltBuffer = [u8; SHA256_OUTPUT_LEN];
 pub type NonceBuffer = [u8; NONCE_LEN];
 pub type KeyBuffer = [u8; SHA256_OUTPUT_LEN];
 
-fn erase<T: Into<Vec<u8>>>(s: T) {
-    let mut buffer: Vec<u8> = s.into();
-    buffer.fill(0);
-}
+/// Current version of the `.rpdb` format, 8 bits for the major version and 8 for the minor.
+pub const VERSION: u32 = 0x0009;
 
-enum PasswordType {
-    Alpha,
-    AlphaNum,
-    All,
-}
-
-static CHARS: [char; 67] = [
-    'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r', 's',
-    't', 'u', 'v', 'w', 'x', 'y', 'z', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L',
-    'M', 'N', 'O', 'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', '0', '1', '2', '3', '4',
-    '5', '6', '7', '8', '9', '!', '#', '%', '$', '@',
-];
-
-static ALPHA_BOUND: usize = 51;
-static ALPHA_NUM_BOUND: usize = 61;
-static ALL_BOUND: usize = 66;
+/// Name of the key slot of a new vault, or of one converted from a single master key.
+pub const DEFAULT_SLOT: &str = "default";
 
 impl KeyGen {
-    pub fn generate_password(len: usize, pwd_type: PasswordType) -> Result<String> {
-        let rng = StdRng::from_os_rng();
-
-        type P = PasswordType;
-        let distrib = match pwd_type {
-            P::Alpha => Uniform::new_inclusive(0, ALPHA_BOUND),
-            P::AlphaNum => Uniform::new_inclusive(0, ALPHA_NUM_BOUND),
-            P::All => Uniform::new_inclusive(0, ALL_BOUND),
-        }?;
-        let buf: String = distrib
-            .sample_iter(rng)
-            .take(len)
-            .map(|i| CHARS[i])
+    /// Generates a password following `policy`. Passwords missing a required class are drawn
+    /// again, so that every accepted password is equally likely.
+    pub fn generate_password(policy: &PasswordPolicy) -> Result<String> {
+        let mut rng = OsRng.unwrap_err();
+        let charset = policy.charset();
+        let distrib = Uniform::new(0, charset.len())?;
+        loop {
+            let password: String = (&distrib)
+                .sample_iter(&mut rng)
+                .take(policy.length)
+                .map(|i| charset[i])
+                .collect();
+            if policy.accepts(&password) {
+                return Ok(password);
+            }
+        }
+    }
+
+    /// Generates a passphrase of words drawn uniformly from the wordlist of `policy`.
+    pub fn generate_passphrase(policy: &PassphrasePolicy) -> Result<String> {
+        let mut rng = OsRng.unwrap_err();
+        let wordlist = &policy.wordlist;
+        let distrib = Uniform::new(0, wordlist.len())?;
+        let mut words: Vec<String> = (&distrib)
+            .sample_iter(&mut rng)
+            .take(policy.words)
+            .map(|i| match policy.capitalize {
+                true => capitalize(&wordlist[i]),
+                false => wordlist[i].clone(),
+            })
             .collect();
-        Ok(buf)
+        if policy.digit {
+            let word = Uniform::new(0, words.len())?.sample(&mut rng);
+            let digit = Uniform::new(0, 10)?.sample(&mut rng);
+            words[word].push_str(&digit.to_string());
+        }
+        Ok(words.join(&policy.separator))
+    }
+
+    /// Builds the Argon2 instance described by `params`.
+    pub fn argon2(params: &KdfParams) -> Result<Argon2<'static>> {
+        let algorithm = match params.algorithm.enum_value().map_err(|_| {
+            RustPassError::Corrupted(String::from("Unknown key derivation algorithm"))
+        })? {
+            KdfAlgorithm::ARGON2D => Algorithm::Argon2d,
+            KdfAlgorithm::ARGON2I => Algorithm::Argon2i,
+            KdfAlgorithm::ARGON2ID => Algorithm::Argon2id,
+        };
+        let params = Params::new(
+            params.memory,
+            params.iterations,
+            params.parallelism,
+            Some(SHA256_OUTPUT_LEN),
+        )
+        .map_err(|e| {
+            RustPassError::InvalidInput(format!("Invalid key derivation parameters: {}", e))
+        })?;
+        Ok(Argon2::new(algorithm, Version::V0x13, params))
     }
-    pub fn encrypt_master(master_key: String, salt: &SaltBuffer) -> Result<KeyBuffer> {
+
+    /// Hashes the master key with Argon2. Vaults that predate stored parameters pass `None` and
+    /// get the argon2 crate defaults.
+    pub fn hash_master_key(
+        master_key: &MasterKey,
+        salt: &[u8],
+        params: Option<&KdfParams>,
+    ) -> Result<KeyBuffer> {
+        let argon2 = match params {
+            Some(params) => Self::argon2(params)?,
+            None => Argon2::default(),
+        };
         let mut key = SaltBuffer::default();
-        Argon2::default()
-            .hash_password_into(&master_key.as_bytes(), salt, &mut key)
-            .map_err(|_| anyhow!("Could not generate argon2 hash"))?;
+        let mut input = master_key.argon_input();
+        let result = argon2
+            .hash_password_into(&input, salt, &mut key)
+            .map_err(|_| RustPassError::Crypto(String::from("Could not generate argon2 hash")));
 
-        erase(master_key);
+        input.fill(0);
+        result?;
         Ok(key)
     }
 
     pub fn derive_key(key: &[u8], salt: &[u8]) -> Result<LessSafeKey> {
         if salt.len() != SHA256_OUTPUT_LEN {
-            return Err(anyhow!("Invalid salt length"));
+            return Err(RustPassError::Corrupted(String::from("Invalid salt length")).into());
         }
 
         let salt = Salt::new(HKDF_SHA256, salt);
         let prk = salt.extract(key);
         let okm = prk
             .expand(&INFO, HKDF_SHA256)
-            .map_err(|_| anyhow!("Could not expand prk"))?;
+            .map_err(|_| RustPassError::Crypto(String::from("Could not expand prk")))?;
         let mut buf = SaltBuffer::default();
         okm.fill(&mut buf)
-            .map_err(|_| anyhow!("Could not fill buffer"))?;
+            .map_err(|_| RustPassError::Crypto(String::from("Could not fill buffer")))?;
         let unbound = UnboundKey::new(&AES_256_GCM, &buf)
-            .map_err(|_| anyhow!("Could not create UnboundKey"))?;
+            .map_err(|_| RustPassError::Crypto(String::from("Could not create UnboundKey")))?;
         Ok(LessSafeKey::new(unbound))
     }
 
+    pub fn random_key() -> Result<KeyBuffer> {
+        let mut buf = KeyBuffer::default();
+        SystemRandom::new()
+            .fill(&mut buf)
+            .map_err(|_| RustPassError::Crypto(String::from("Could not generate key")))?;
+        Ok(buf)
+    }
+
     pub fn get_unique_nonce() -> Result<NonceBuffer> {
         let mut buf = NonceBuffer::default();
         let rng = SystemRandom::new();
         rng.fill(&mut buf)
-            .map_err(|_| anyhow!("Coult not generate nonce"))?;
+            .map_err(|_| RustPassError::Crypto(String::from("Could not generate nonce")))?;
         Ok(buf)
     }
 }
@@ -128,7 +305,13 @@ impl KeyGen {
 pub struct VaultManager {
     header: Header,
     body: Body,
-    master_hash: KeyBuffer,
+    /// Random key every other key is derived from. Before version 0x0008, this was the Argon2 hash
+    /// of the master key.
+    data_key: KeyBuffer,
+    /// Index of the key slot the vault was unlocked with
+    slot: usize,
+    key_file: Option<KeyBuffer>,
+    config: LocalConfig,
 }
 
 #[derive(Default, Debug)]
@@ -136,7 +319,6 @@ pub struct Salts {
     master_nonce: NonceBuffer,
     master_salt: SaltBuffer,
     body_salt: SaltBuffer,
-    argon_salt: SaltBuffer,
 }
 
 impl Salts {
@@ -145,136 +327,608 @@ impl Salts {
 
         let mut instance = Self::default();
         rng.fill(&mut instance.master_salt)
-            .map_err(|_| anyhow!("Could not generate salt"))?;
+            .map_err(|_| RustPassError::Crypto(String::from("Could not generate salt")))?;
         rng.fill(&mut instance.body_salt)
-            .map_err(|_| anyhow!("Could not generate salt"))?;
-        rng.fill(&mut instance.argon_salt)
-            .map_err(|_| anyhow!("Could not generate salt"))?;
+            .map_err(|_| RustPassError::Crypto(String::from("Could not generate salt")))?;
         rng.fill(&mut instance.master_nonce)
-            .map_err(|_| anyhow!("Could not generate nonce"))?;
+            .map_err(|_| RustPassError::Crypto(String::from("Could not generate nonce")))?;
 
         Ok(instance)
     }
 }
 
 impl VaultManager {
-    fn encrypt(&self) -> Result<RPDB> {
-        let key = KeyGen::derive_key(&self.master_hash, &self.header.master_salt)?;
-        let nonce = Nonce::assume_unique_for_key(self.header.master_nonce.as_slice().try_into()?);
-        let aad = Aad::from(self.header.write_to_bytes()?);
-        let mut rpdb = RPDB::new();
-        rpdb.body = self.body.write_to_bytes()?;
-        key.seal_in_place_append_tag(nonce, aad, &mut rpdb.body)
-            .map_err(|_| anyhow!("Could not seal body"))?;
-        rpdb.header = MessageField::some(self.header.clone());
-        Ok(rpdb)
+    pub fn new(config: LocalConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Seals the body and writes the vault in the block format.
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let header = self.header.write_to_bytes()?;
+        let key = KeyGen::derive_key(&self.data_key, &self.header.master_salt)?;
+        let nonce = stored_nonce(&self.header.master_nonce)?;
+        let mut body = self.body.write_to_bytes()?;
+        key.seal_in_place_append_tag(nonce, Aad::from(header.as_slice()), &mut body)
+            .map_err(|_| RustPassError::Crypto(String::from("Could not seal body")))?;
+
+        let keys = BlockKeys::new(&self.header.master_salt, &self.data_key);
+        blocks::write_header(writer, &header, &keys)?;
+        blocks::write_blocks(writer, &body, self.header.chunk_size as usize, &keys)?;
+        writer.flush()?;
+        Ok(())
     }
 
     pub fn initialize_from_file<P: AsRef<Path>>(
         &mut self,
         path: P,
-        master_key: String,
+        master_key: impl Into<MasterKey>,
     ) -> Result<()> {
-        let mut buf: Vec<u8> = vec![];
-        let mut file = File::open(path)?;
-        let bytes_read = file.read_to_end(&mut buf)?;
-        if bytes_read == 0 {
-            return Err(anyhow!("Read 0 bytes from file"));
+        let master_key = master_key.into();
+        self.read_vault(path.as_ref(), &master_key)?;
+        if self.header.key_slots.is_empty() {
+            self.convert_to_slots(&master_key)?;
         }
-        let mut rpdb = RPDB::parse_from_bytes(&buf)?;
+        Ok(())
+    }
+
+    fn read_vault(&mut self, path: &Path, master_key: &MasterKey) -> Result<()> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut signature = [0; 4];
+        reader.read_exact(&mut signature).map_err(|_| {
+            RustPassError::Corrupted(String::from("File is too short to be a vault"))
+        })?;
+        if u32::from_le_bytes(signature) != SIGNATURE {
+            let mut buf = signature.to_vec();
+            reader.read_to_end(&mut buf)?;
+            return self.initialize_from_legacy(&buf, master_key);
+        }
+
+        let (header, mac) = blocks::read_header(&mut reader)?;
+        self.header = Header::parse_from_bytes(&header)
+            .map_err(|_| RustPassError::Corrupted(String::from("Could not parse header")))?;
+        self.check_version()?;
+        self.unlock(master_key)?;
+
+        let keys = BlockKeys::new(&self.header.master_salt, &self.data_key);
+        blocks::verify_header(&header, &mac, &keys).map_err(|e| self.unchecked_key_error(e))?;
+        let mut body = blocks::read_blocks(&mut reader, &keys)?;
+        self.open_body(&header, &mut body)
+    }
+
+    /// Reads a vault written before version 0x0004, stored as a single `RPDB` protobuf.
+    fn initialize_from_legacy(&mut self, buf: &[u8], master_key: &MasterKey) -> Result<()> {
+        let mut rpdb = RPDB::parse_from_bytes(buf)
+            .map_err(|_| RustPassError::Corrupted(String::from("File is not a vault")))?;
         self.header = rpdb
             .header
             .into_option()
-            .ok_or(anyhow!("Could not parse header"))?;
+            .ok_or(RustPassError::Corrupted(String::from(
+                "Could not parse header",
+            )))?;
+        self.check_version()?;
+        self.unlock(master_key)?;
+
+        let aad = self.header.write_to_bytes()?;
+        let result = self.open_body(&aad, &mut rpdb.body);
+        result.map_err(|e| self.unchecked_key_error(e))
+    }
+
+    /// Vaults without key slots derive their data key from the master key without checking it, so
+    /// a wrong master password only shows up as a corrupted header or body. It is reported as
+    /// such, being far more likely than actual corruption.
+    fn unchecked_key_error(&self, e: anyhow::Error) -> anyhow::Error {
+        let corrupted = matches!(e.downcast_ref(), Some(RustPassError::Corrupted(_)));
+        match corrupted && self.header.key_slots.is_empty() {
+            true => RustPassError::WrongPassword.into(),
+            false => e,
+        }
+    }
+
+    fn check_version(&self) -> Result<()> {
+        if self.header.version > VERSION {
+            return Err(RustPassError::UnsupportedVersion {
+                found: self.header.version,
+                supported: VERSION,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Recovers the data key from the first key slot that `master_key` unwraps.
+    fn unlock(&mut self, master_key: &MasterKey) -> Result<()> {
+        if self.header.key_slots.is_empty() {
+            return self.derive_legacy_data_key(master_key);
+        }
+        let uses_key_file = master_key.key_file().is_some();
+        let mut matching = self
+            .header
+            .key_slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.uses_key_file() == uses_key_file)
+            .peekable();
+        if matching.peek().is_none() {
+            return Err(RustPassError::KeyFileMismatch {
+                required: !uses_key_file,
+            }
+            .into());
+        }
+        let (index, data_key) = matching
+            .find_map(|(index, slot)| slot.unwrap(master_key).ok().map(|key| (index, key)))
+            .ok_or(RustPassError::WrongPassword)?;
+        self.data_key = data_key;
+        self.slot = index;
+        self.key_file = master_key.key_file().copied();
+        Ok(())
+    }
 
-        self.master_hash =
-            KeyGen::encrypt_master(master_key, self.header.argon_salt.as_slice().try_into()?)?;
+    /// Derives the data key of a vault written before version 0x0008, which was the hash of its
+    /// only master key.
+    fn derive_legacy_data_key(&mut self, master_key: &MasterKey) -> Result<()> {
+        match (
+            slots::uses_key_file(&self.header.key_components),
+            master_key.key_file().is_some(),
+        ) {
+            (true, false) => return Err(RustPassError::KeyFileMismatch { required: true }.into()),
+            (false, true) => return Err(RustPassError::KeyFileMismatch { required: false }.into()),
+            _ => {}
+        }
+        self.key_file = master_key.key_file().copied();
+        self.data_key = KeyGen::hash_master_key(
+            &master_key.encode(PasswordEncoding::LINE),
+            &self.header.argon_salt,
+            self.header.kdf_params.as_ref(),
+        )?;
+        Ok(())
+    }
 
-        let nonce = Nonce::assume_unique_for_key(self.header.master_nonce.as_slice().try_into()?);
-        let key = KeyGen::derive_key(&self.master_hash, &self.header.master_salt)?;
-        let aad = Aad::from(self.header.write_to_bytes()?);
+    /// Wraps the data key of a vault written before version 0x0008 in a first key slot, so the
+    /// vault keeps opening with the same master key and its records stay as they are.
+    fn convert_to_slots(&mut self, master_key: &MasterKey) -> Result<()> {
+        let params = match self.header.kdf_params.as_ref() {
+            Some(params) => params.clone(),
+            None => KdfParams::from(&self.config),
+        };
+        let slot = KeySlot::wrap(
+            DEFAULT_SLOT,
+            master_key,
+            &self.data_key,
+            params,
+            PasswordEncoding::from(&self.config),
+        )?;
+        self.header.key_slots = vec![slot];
+        self.header.argon_salt.clear();
+        self.header.kdf_params = MessageField::none();
+        self.header.key_components.clear();
+        self.slot = 0;
+        Ok(())
+    }
+
+    fn open_body(&mut self, aad: &[u8], sealed: &mut [u8]) -> Result<()> {
+        let nonce = stored_nonce(&self.header.master_nonce)?;
+        let key = KeyGen::derive_key(&self.data_key, &self.header.master_salt)?;
         let decrypted_body = key
-            .open_in_place(nonce, aad, &mut rpdb.body)
-            .map_err(|_| anyhow!("Could not decrypt body"))?;
-        self.body = Body::parse_from_bytes(decrypted_body)?;
+            .open_in_place(nonce, Aad::from(aad), sealed)
+            .map_err(|_| RustPassError::Corrupted(String::from("Could not decrypt body")))?;
+        self.body = Body::parse_from_bytes(decrypted_body)
+            .map_err(|_| RustPassError::Corrupted(String::from("Could not parse body")))?;
+        self.migrate()?;
+        self.purge_trash();
         Ok(())
     }
 
-    pub fn regenerate(&mut self, master_key: String) -> Result<()> {
+    pub fn regenerate(&mut self, master_key: impl Into<MasterKey>) -> Result<()> {
+        let master_key = master_key.into();
         let salts = Salts::new()?;
-        self.header.signature = 0x3af9c42;
+        self.data_key = KeyGen::random_key()?;
+        self.header.signature = SIGNATURE;
         self.header.master_salt = salts.master_salt.to_vec();
-        self.header.version = 0x0001;
+        self.header.version = VERSION;
         self.header.master_nonce = salts.master_nonce.to_vec();
-        self.header.argon_salt = salts.argon_salt.to_vec();
+        self.header.key_slots = vec![KeySlot::wrap(
+            DEFAULT_SLOT,
+            &master_key,
+            &self.data_key,
+            KdfParams::from(&self.config),
+            PasswordEncoding::from(&self.config),
+        )?];
+        self.slot = 0;
+        self.key_file = master_key.key_file().copied();
         self.body.salt = salts.body_salt.to_vec();
+        self.body.root = MessageField::some(Directory::new());
         self.body.created_at = MessageField::some(Timestamp::now());
         self.body.last_modified = MessageField::some(Timestamp::now());
-        self.master_hash = KeyGen::encrypt_master(master_key, &salts.argon_salt)?;
         Ok(())
     }
 
-    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+    /// Checks `password` against the key slot the vault was unlocked with. The key file the vault
+    /// was opened with, if any, is used along with it.
+    pub fn verify_master_password(&self, password: String) -> Result<()> {
+        self.current_slot()?
+            .unwrap(&MasterKey::new(password, self.key_file))
+            .map(|_| ())
+    }
+
+    /// Replaces the master key of the key slot the vault was unlocked with.
+    ///
+    /// When it is the only slot, the data key is replaced as well: every record, including
+    /// previous versions and trashed ones, is sealed again under a record key derived from a fresh
+    /// body salt, and the header keys are renewed on the next save. Other slots would be locked
+    /// out by a new data key, so with several slots only this one is wrapped again.
+    pub fn change_master_password(&mut self, master_key: impl Into<MasterKey>) -> Result<()> {
+        let master_key = master_key.into();
+        let current = self.current_slot()?;
+        let name = current.name.clone();
+        let kdf_params = match current.kdf_params.as_ref() {
+            Some(params) => params.clone(),
+            None => KdfParams::from(&self.config),
+        };
+
+        let mut data_key = self.data_key;
+        let mut body = None;
+        if self.header.key_slots.len() == 1 {
+            let salts = Salts::new()?;
+            data_key = KeyGen::random_key()?;
+            let old_key = KeyGen::derive_key(&self.data_key, &self.body.salt)?;
+            let new_key = KeyGen::derive_key(&data_key, &salts.body_salt)?;
+            let mut resealed = self.body.clone();
+            for_each_body_record(&mut resealed, &mut |record| {
+                record.reseal(&old_key, &new_key)
+            })?;
+            resealed.salt = salts.body_salt.to_vec();
+            body = Some(resealed);
+        }
+        let slot = KeySlot::wrap(
+            &name,
+            &master_key,
+            &data_key,
+            kdf_params,
+            PasswordEncoding::from(&self.config),
+        )?;
+
+        if let Some(body) = body {
+            self.body = body;
+        }
+        self.header.key_slots[self.slot] = slot;
+        self.key_file = master_key.key_file().copied();
+        self.data_key = data_key;
+        Ok(())
+    }
+
+    pub fn slots(&self) -> &[KeySlot] {
+        &self.header.key_slots
+    }
+
+    /// Index of the key slot the vault was unlocked with.
+    pub fn slot_index(&self) -> usize {
+        self.slot
+    }
+
+    fn current_slot(&self) -> Result<&KeySlot> {
+        self.header
+            .key_slots
+            .get(self.slot)
+            .ok_or(RustPassError::Corrupted(String::from("The vault has no key slot")).into())
+    }
+
+    /// Adds a key slot named `name` which unlocks the vault with `master_key`, hashed with the
+    /// parameters of the local configuration.
+    pub fn add_slot(&mut self, name: &str, master_key: impl Into<MasterKey>) -> Result<()> {
+        if name.is_empty() {
+            return Err(
+                RustPassError::InvalidInput(String::from("Key slot name cannot be empty")).into(),
+            );
+        }
+        if self.header.key_slots.iter().any(|slot| slot.name == name) {
+            return Err(RustPassError::AlreadyExists(format!("Key slot {}", name)).into());
+        }
+        let slot = KeySlot::wrap(
+            name,
+            &master_key.into(),
+            &self.data_key,
+            KdfParams::from(&self.config),
+            PasswordEncoding::from(&self.config),
+        )?;
+        self.header.key_slots.push(slot);
+        Ok(())
+    }
+
+    /// Removes a key slot. The data key stays the same, so copies of the vault made before the
+    /// slot was revoked still open with it.
+    pub fn revoke_slot(&mut self, index: usize) -> Result<KeySlot> {
+        if index >= self.header.key_slots.len() {
+            return Err(RustPassError::NotFound(format!("Key slot at index {}", index)).into());
+        }
+        if index == self.slot {
+            return Err(RustPassError::InvalidInput(String::from(
+                "Cannot revoke the key slot this session was unlocked with",
+            ))
+            .into());
+        }
+        if index < self.slot {
+            self.slot -= 1;
+        }
+        Ok(self.header.key_slots.remove(index))
+    }
+
+    /// Key file the vault was opened or created with.
+    pub fn key_file(&self) -> Option<KeyBuffer> {
+        self.key_file
+    }
+
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
         if let Some(path) = path.as_ref().parent() {
             if !path.exists() {
                 //create_dir_all(path)?; NOTE: may want to keep this
-                return Err(anyhow!("Invalid directory"));
+                return Err(RustPassError::InvalidInput(String::from("Invalid directory")).into());
             }
         }
-        let mut file = File::create(path)?;
-        file.write(&self.encrypt()?.write_to_bytes()?)?;
-        Ok(())
+        // Each save seals a different body, so the nonce of the previous one can never be reused.
+        // The master salt is replaced along with it, which also renews the body and HMAC keys.
+        let salts = Salts::new()?;
+        self.header.master_nonce = salts.master_nonce.to_vec();
+        self.header.master_salt = salts.master_salt.to_vec();
+        self.header.chunk_size = self.config.chunk_size.saturating_mul(1024);
+        self.body.last_modified = MessageField::some(Timestamp::now());
+
+        let path = path.as_ref();
+        storage::write_atomically(path, self.config.backups, |writer| self.write_to(writer))
     }
 
-    pub fn add_directory(&mut self, name: &str) -> () {
+    pub fn add_directory(&mut self, path: &str) -> Result<()> {
+        let (parent, name) = split_parent(path)?;
+        let parent = find_dir_mut(self.body.root.mut_or_insert_default(), &parent)
+            .ok_or(RustPassError::NotFound(String::from("Directory")))?;
+        if parent.directories.iter().any(|dir| dir.name == name) {
+            return Err(RustPassError::AlreadyExists(String::from("Directory")).into());
+        }
         let mut dir = Directory::new();
         dir.name = name.into();
-        self.body.directories.push(dir);
+        parent.directories.push(dir);
+        Ok(())
     }
 
-    pub fn remove_directory(&mut self, name: &str) -> Result<()> {
-        if let Some(index) = self
-            .body
+    /// Moves a directory and everything inside it to the trash.
+    pub fn remove_directory(&mut self, path: &str) -> Result<()> {
+        let (parent_path, name) = split_parent(path)?;
+        let parent = find_dir_mut(self.body.root.mut_or_insert_default(), &parent_path)
+            .ok_or(RustPassError::NotFound(String::from("Directory")))?;
+        let index = parent
             .directories
             .iter()
             .position(|dir| dir.name == name)
-        {
-            self.body.directories.remove(index);
-            return Ok(());
+            .ok_or(RustPassError::NotFound(String::from("Directory")))?;
+        let mut item = TrashItem::new();
+        item.set_directory(parent.directories.remove(index));
+        self.add_to_trash(parent_path.join("/"), item);
+        Ok(())
+    }
+
+    /// Moves the record `name` of the directory at `path` to the trash.
+    pub fn remove_record(&mut self, path: &str, name: &str) -> Result<()> {
+        let record = self.open_dir(path)?.remove_record(name)?;
+        let mut item = TrashItem::new();
+        item.set_record(record);
+        self.add_to_trash(split_path(path).join("/"), item);
+        Ok(())
+    }
+
+    fn add_to_trash(&mut self, path: String, mut item: TrashItem) {
+        item.path = path;
+        item.deleted_at = MessageField::some(Timestamp::now());
+        self.body.trash.push(item);
+    }
+
+    /// Items in the trash, oldest first.
+    pub fn trash(&self) -> &[TrashItem] {
+        &self.body.trash
+    }
+
+    /// Puts the `index`'th item of the trash back where it was removed from, recreating the
+    /// directories leading to it if they were removed since.
+    pub fn restore_from_trash(&mut self, index: usize) -> Result<()> {
+        let item = self
+            .body
+            .trash
+            .get(index)
+            .ok_or(RustPassError::NotFound(String::from("Trash item")))?;
+        let path = item.path.clone();
+        let components = split_path(&path);
+        for depth in 1..=components.len() {
+            let ancestor = components[..depth].join("/");
+            if self.open_dir(&ancestor).is_err() {
+                self.add_directory(&ancestor)?;
+            }
+        }
+
+        let body = &mut self.body;
+        let parent = find_dir_mut(body.root.mut_or_insert_default(), &components)
+            .ok_or(RustPassError::NotFound(String::from("Directory")))?;
+        let exists = match &body.trash[index].item {
+            Some(Item::Record(record)) => parent.records.iter().any(|r| r.name == record.name),
+            Some(Item::Directory(dir)) => parent.directories.iter().any(|d| d.name == dir.name),
+            None => {
+                return Err(
+                    RustPassError::Corrupted(String::from("Trash item is corrupted")).into(),
+                )
+            }
+        };
+        if exists {
+            return Err(RustPassError::AlreadyExists(format!(
+                "{} in /{}",
+                trash_item_name(&body.trash[index]),
+                path
+            ))
+            .into());
+        }
+        match body.trash.remove(index).item {
+            Some(Item::Record(record)) => parent.records.push(record),
+            Some(Item::Directory(dir)) => parent.directories.push(dir),
+            None => {}
+        }
+        Ok(())
+    }
+
+    pub fn empty_trash(&mut self) {
+        self.body.trash.clear();
+    }
+
+    /// Permanently deletes the items that stayed in the trash longer than the configured
+    /// retention period.
+    fn purge_trash(&mut self) {
+        if self.config.trash_retention == 0 {
+            return;
         }
-        Err(anyhow!("Directory does not exist"))
+        let retention = i64::from(self.config.trash_retention) * 86400;
+        let cutoff = Timestamp::now().seconds - retention;
+        self.body.trash.retain(|item| {
+            item.deleted_at
+                .as_ref()
+                .is_none_or(|deleted_at| deleted_at.seconds >= cutoff)
+        });
     }
 
-    pub fn open_dir(&mut self, dir_name: &str) -> Result<DirectoryManager> {
-        if let Some(dir) = self
+    /// Opens the directory at `path`, relative to the root of the vault. An empty path opens
+    /// the root itself.
+    pub fn open_dir(&mut self, path: &str) -> Result<DirectoryManager<'_>> {
+        let salt = self
             .body
-            .directories
-            .iter_mut()
-            .find(|dir| dir.name == dir_name)
-        {
-            return Ok(DirectoryManager::new(
-                dir,
-                self.body.salt.as_slice().try_into()?,
-                &self.master_hash,
-            ));
+            .salt
+            .as_slice()
+            .try_into()
+            .map_err(|_| RustPassError::Corrupted(String::from("Invalid body salt")))?;
+        if let Some(dir) = find_dir_mut(self.body.root.mut_or_insert_default(), &split_path(path)) {
+            return Ok(DirectoryManager::new(dir, salt, &self.data_key)
+                .with_history(self.config.history as usize));
         }
-        return Err(anyhow!("Could not find directory"));
+        Err(RustPassError::NotFound(String::from("Directory")).into())
     }
 
-    pub fn get_directories(&mut self) -> Vec<&str> {
-        self.body
-            .directories
+    pub fn rename_directory(&mut self, path: &str, new_name: &str) -> Result<()> {
+        let (parent, _) = split_parent(path)?;
+        let parent = find_dir_mut(self.body.root.mut_or_insert_default(), &parent)
+            .ok_or(RustPassError::NotFound(String::from("Directory")))?;
+        if parent.directories.iter().any(|dir| dir.name == new_name) {
+            return Err(RustPassError::AlreadyExists(String::from("Directory")).into());
+        }
+        self.open_dir(path)?.rename(new_name);
+        Ok(())
+    }
+
+    /// Moves a record between two directories. The sealed data is carried over as is, since it
+    /// is bound to the record name rather than to its directory.
+    pub fn move_record(&mut self, from: &str, name: &str, to: &str) -> Result<()> {
+        let root = self.body.root.mut_or_insert_default();
+        let to_path = split_path(to);
+        let to_dir =
+            find_dir(root, &to_path).ok_or(RustPassError::NotFound(String::from("Directory")))?;
+        if to_dir.records.iter().any(|record| record.name == name) {
+            return Err(RustPassError::AlreadyExists(format!("Key in {}", to)).into());
+        }
+
+        let from_dir = find_dir_mut(root, &split_path(from))
+            .ok_or(RustPassError::NotFound(String::from("Directory")))?;
+        let index = from_dir
+            .records
             .iter()
-            .map(|dir| dir.name.as_str())
-            .collect()
+            .position(|record| record.name == name)
+            .ok_or(RustPassError::NotFound(String::from("Key")))?;
+        let record = from_dir.records.remove(index);
+        find_dir_mut(root, &to_path)
+            .ok_or(RustPassError::NotFound(String::from("Directory")))?
+            .records
+            .push(record);
+        Ok(())
+    }
+
+    /// Brings a vault written by an older version up to the current format.
+    fn migrate(&mut self) -> Result<()> {
+        if self.header.version < 0x0002 {
+            // Flat vaults kept their directories directly inside the body.
+            let directories = std::mem::take(&mut self.body.directories);
+            self.body
+                .root
+                .mut_or_insert_default()
+                .directories
+                .extend(directories);
+        }
+        if self.header.version < 0x0005 {
+            // Record nonces used to be derived from the position of the record, so the same
+            // nonce was reused across directories. Seal everything again under random ones.
+            let key = KeyGen::derive_key(&self.data_key, &self.body.salt)?;
+            for_each_record(self.body.root.mut_or_insert_default(), &mut |record| {
+                let value = record.open(&key)?;
+                record.seal(&key, &value)
+            })?;
+        }
+        if self.header.version < 0x0006 {
+            // Records used to seal the bare password. Wrap it into an entry, dated back to the
+            // last modification of the vault.
+            let key = KeyGen::derive_key(&self.data_key, &self.body.salt)?;
+            let last_modified = self.body.last_modified.clone();
+            for_each_record(self.body.root.mut_or_insert_default(), &mut |record| {
+                let mut entry = Entry::new();
+                entry.password = String::from_utf8(record.open(&key)?).map_err(|_| {
+                    RustPassError::Corrupted(format!("Key {} is not valid UTF-8", record.name))
+                })?;
+                record.seal_entry(&key, &entry)?;
+                record.created_at = last_modified.clone();
+                record.modified_at = last_modified.clone();
+                Ok(())
+            })?;
+        }
+        self.header.version = VERSION;
+        Ok(())
+    }
+}
+
+/// Name of a trashed item, with a trailing `/` for directories.
+pub fn trash_item_name(item: &TrashItem) -> String {
+    match &item.item {
+        Some(Item::Record(record)) => record.name.clone(),
+        Some(Item::Directory(dir)) => format!("{}/", dir.name),
+        None => String::new(),
     }
 }
 
+/// Splits a `/` separated directory path into its components, so that `a/b` and `/a/b/` both
+/// refer to the same directory.
+pub fn split_path(path: &str) -> Vec<&str> {
+    path.split('/').filter(|name| !name.is_empty()).collect()
+}
+
+fn split_parent(path: &str) -> Result<(Vec<&str>, &str)> {
+    let mut components = split_path(path);
+    let name = components
+        .pop()
+        .ok_or(RustPassError::InvalidInput(String::from(
+            "Invalid directory path",
+        )))?;
+    Ok((components, name))
+}
+
+fn find_dir<'a>(root: &'a Directory, path: &[&str]) -> Option<&'a Directory> {
+    path.iter().try_fold(root, |dir, name| {
+        dir.directories.iter().find(|child| child.name == *name)
+    })
+}
+
+fn find_dir_mut<'a>(root: &'a mut Directory, path: &[&str]) -> Option<&'a mut Directory> {
+    path.iter().try_fold(root, |dir, name| {
+        dir.directories.iter_mut().find(|child| child.name == *name)
+    })
+}
+
 #[derive(Debug, PartialEq)]
 pub struct DirectoryManager<'a> {
     dir: &'a mut Directory,
     salt: &'a SaltBuffer,
     master_key: &'a KeyBuffer,
+    history: usize,
 }
 
 impl<'a> DirectoryManager<'a> {
@@ -283,63 +937,180 @@ impl<'a> DirectoryManager<'a> {
             dir,
             salt,
             master_key: key,
+            history: 0,
         }
     }
 
+    /// Keeps up to `depth` previous versions of a record when its entry is changed.
+    pub fn with_history(mut self, depth: usize) -> Self {
+        self.history = depth;
+        self
+    }
+
+    /// Adds a record holding only a password.
     pub fn add_record(&mut self, name: &str, key_val: &str) -> Result<()> {
-        let nonce_buf = generate_nonce_buf(self.salt, &self.dir.name, self.dir.records.len())?;
+        let mut entry = Entry::new();
+        entry.password = key_val.into();
+        self.add_entry(name, &entry)
+    }
+
+    pub fn add_entry(&mut self, name: &str, entry: &Entry) -> Result<()> {
+        if self.dir.records.iter().any(|record| record.name == name) {
+            return Err(RustPassError::AlreadyExists(String::from("Key")).into());
+        }
         let key = KeyGen::derive_key(self.master_key, self.salt)?;
-        let nonce = Nonce::assume_unique_for_key(nonce_buf);
-        let aad = Aad::from(name);
-        let mut buf: Vec<u8> = key_val.into();
-        key.seal_in_place_append_tag(nonce, aad, &mut buf)
-            .map_err(|_| anyhow!("Could not seal key"))?;
         let mut record = Record::new();
         record.name = name.into();
-        record.nonce = nonce_buf.into();
-        record.data = buf.into();
+        record.seal_entry(&key, entry)?;
+        record.created_at = MessageField::some(Timestamp::now());
+        record.modified_at = record.created_at.clone();
 
         self.dir.records.push(record);
 
         Ok(())
     }
 
-    pub fn get_record(&mut self, name: &str) -> Result<String> {
-        let index = self
-            .dir
+    fn record_index(&self, name: &str) -> Result<usize> {
+        self.dir
             .records
             .iter()
             .position(|record| record.name == name)
-            .ok_or(anyhow!("Key does not exist"))?;
+            .ok_or(RustPassError::NotFound(String::from("Key")).into())
+    }
+
+    /// Returns the record called `name`, whose timestamps can be read without opening it.
+    pub fn record(&self, name: &str) -> Result<&Record> {
+        Ok(&self.dir.records[self.record_index(name)?])
+    }
+
+    /// Opens the entry of a record and marks it as accessed.
+    pub fn get_entry(&mut self, name: &str) -> Result<Entry> {
+        let entry = self.read_entry(name)?;
+        let index = self.record_index(name)?;
+        self.dir.records[index].accessed_at = MessageField::some(Timestamp::now());
+        Ok(entry)
+    }
+
+    /// Opens the entry of a record without marking it as accessed, for reads the user did not
+    /// ask for, such as audits.
+    pub fn read_entry(&self, name: &str) -> Result<Entry> {
+        let key = KeyGen::derive_key(self.master_key, self.salt)?;
+        self.record(name)?.open_entry(&key)
+    }
+
+    /// Returns the password of a record.
+    pub fn get_record(&mut self, name: &str) -> Result<String> {
+        Ok(self.get_entry(name)?.password)
+    }
+
+    pub fn get_field(&mut self, name: &str, field: &EntryField) -> Result<Option<String>> {
+        Ok(self.get_entry(name)?.get(field))
+    }
 
-        let record = &self.dir.records[index];
-        let nonce = Nonce::assume_unique_for_key(record.nonce.as_slice().try_into()?);
-        let aad = Aad::from(name);
+    pub fn set_field(&mut self, name: &str, field: &EntryField, value: &str) -> Result<()> {
+        self.update_entry(name, |entry| entry.set(field, value))
+    }
+
+    /// Applies `update` to the entry of a record and seals it again.
+    pub fn update_entry<F>(&mut self, name: &str, update: F) -> Result<()>
+    where
+        F: FnOnce(&mut Entry) -> Result<()>,
+    {
+        let index = self.record_index(name)?;
         let key = KeyGen::derive_key(self.master_key, self.salt)?;
-        let mut buf: Vec<u8> = record.data.clone();
+        let record = &mut self.dir.records[index];
+        let mut entry = record.open_entry(&key)?;
+        update(&mut entry)?;
 
-        let decrypted = key
-            .open_in_place(nonce, aad, &mut buf)
-            .map_err(|_| anyhow!("Could not open key"))?;
+        let mut version = RecordVersion::new();
+        version.nonce = std::mem::take(&mut record.nonce);
+        version.data = std::mem::take(&mut record.data);
+        version.modified_at = record.modified_at.clone();
+        record.history.insert(0, version);
+        record.history.truncate(self.history);
 
-        Ok(String::from_utf8(decrypted.to_vec())?)
+        record.seal_entry(&key, &entry)?;
+        record.modified_at = MessageField::some(Timestamp::now());
+        Ok(())
     }
 
-    pub fn remove_record(&mut self, name: &str) -> Result<()> {
-        let index = self
+    /// Opens the previous versions of a record, most recent first.
+    pub fn get_history(&self, name: &str) -> Result<Vec<Entry>> {
+        let key = KeyGen::derive_key(self.master_key, self.salt)?;
+        let record = self.record(name)?;
+        record
+            .history
+            .iter()
+            .map(|version| decode_entry(open_value(&key, name, &version.nonce, &version.data)?))
+            .collect()
+    }
+
+    /// Replaces the entry of a record with its `index`'th previous version, counting from 0 for
+    /// the most recent one. The replaced entry is kept in the history like any other change.
+    pub fn restore_version(&mut self, name: &str, index: usize) -> Result<()> {
+        let mut history = self.get_history(name)?;
+        if index >= history.len() {
+            return Err(RustPassError::NotFound(String::from("Version")).into());
+        }
+        let restored = history.swap_remove(index);
+        self.update_entry(name, |entry| {
+            *entry = restored;
+            Ok(())
+        })
+    }
+
+    /// Sets or clears the date after which a record should be replaced.
+    pub fn set_expiry(&mut self, name: &str, expires_at: Option<Timestamp>) -> Result<()> {
+        let index = self.record_index(name)?;
+        let record = &mut self.dir.records[index];
+        record.expires_at = expires_at.into();
+        record.modified_at = MessageField::some(Timestamp::now());
+        Ok(())
+    }
+
+    /// Removes a record from the directory and returns it. See [`VaultManager::remove_record`]
+    /// to move it to the trash instead.
+    pub fn remove_record(&mut self, name: &str) -> Result<Record> {
+        let index = self.record_index(name)?;
+        Ok(self.dir.records.remove(index))
+    }
+
+    /// Renames a record. The record name is part of the authenticated data, so the entry is
+    /// sealed again under the new name.
+    pub fn rename_record(&mut self, name: &str, new_name: &str) -> Result<()> {
+        if self
             .dir
             .records
             .iter()
-            .position(|record| record.name == name)
-            .ok_or(anyhow!("Key does not exist"))?;
-        self.dir.records.remove(index);
-        Ok(())
+            .any(|record| record.name == new_name)
+        {
+            return Err(RustPassError::AlreadyExists(String::from("Key")).into());
+        }
+        let index = self.record_index(name)?;
+        let key = KeyGen::derive_key(self.master_key, self.salt)?;
+        let record = &mut self.dir.records[index];
+        let entry = record.open_entry(&key)?;
+        for version in record.history.iter_mut() {
+            let mut value = open_value(&key, name, &version.nonce, &version.data)?;
+            (version.nonce, version.data) = seal_value(&key, new_name, &value)?;
+            value.fill(0);
+        }
+        record.name = new_name.into();
+        record.seal_entry(&key, &entry)
     }
 
     pub fn rename(&mut self, new_name: &str) {
         self.dir.name = new_name.into();
     }
 
+    pub fn get_directory_names(&self) -> Vec<&str> {
+        self.dir
+            .directories
+            .iter()
+            .map(|dir| dir.name.as_str())
+            .collect()
+    }
+
     pub fn get_record_names(&self) -> Vec<&str> {
         self.dir
             .records
@@ -349,24 +1120,120 @@ impl<'a> DirectoryManager<'a> {
     }
 }
 
-pub fn generate_nonce_buf(
-    salt: &SaltBuffer,
-    directory_name: &str,
-    index: usize,
-) -> Result<NonceBuffer> {
-    let mut key_buf = NonceBuffer::default();
-    let mut salt_buf: Vec<u8> = directory_name.as_bytes().to_vec();
-    salt_buf.extend_from_slice(salt);
-    Argon2::default()
-        .hash_password_into(&index.to_le_bytes(), salt, &mut key_buf)
-        .map_err(|_| anyhow!("Could not generate argon2 hash"))?;
-    Ok(key_buf)
+/// Seals `value` under a fresh random nonce, bound to the name of its record, and returns the
+/// nonce and the sealed data. Nonces are never derived from the position of the record, so they
+/// cannot repeat when records are removed, moved or added again.
+fn seal_value(key: &LessSafeKey, name: &str, value: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let nonce_buf = KeyGen::get_unique_nonce()?;
+    let mut buf = value.to_vec();
+    key.seal_in_place_append_tag(
+        Nonce::assume_unique_for_key(nonce_buf),
+        Aad::from(name),
+        &mut buf,
+    )
+    .map_err(|_| RustPassError::Crypto(String::from("Could not seal key")))?;
+    Ok((nonce_buf.to_vec(), buf))
+}
+
+/// Nonce read back from a vault, which is corrupted if it does not have the right length.
+pub fn stored_nonce(nonce: &[u8]) -> Result<Nonce> {
+    let nonce = nonce
+        .try_into()
+        .map_err(|_| RustPassError::Corrupted(String::from("Invalid nonce")))?;
+    Ok(Nonce::assume_unique_for_key(nonce))
+}
+
+fn open_value(key: &LessSafeKey, name: &str, nonce: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let nonce = stored_nonce(nonce)?;
+    let mut buf = data.to_vec();
+    let decrypted = key
+        .open_in_place(nonce, Aad::from(name), &mut buf)
+        .map_err(|_| RustPassError::Corrupted(String::from("Could not open key")))?;
+    Ok(decrypted.to_vec())
+}
+
+fn decode_entry(mut buf: Vec<u8>) -> Result<Entry> {
+    let entry = Entry::parse_from_bytes(&buf);
+    buf.fill(0);
+    Ok(entry.map_err(|_| RustPassError::Corrupted(String::from("Could not parse entry")))?)
+}
+
+impl Record {
+    fn seal(&mut self, key: &LessSafeKey, value: &[u8]) -> Result<()> {
+        (self.nonce, self.data) = seal_value(key, &self.name, value)?;
+        Ok(())
+    }
+
+    fn open(&self, key: &LessSafeKey) -> Result<Vec<u8>> {
+        open_value(key, &self.name, &self.nonce, &self.data)
+    }
+
+    fn seal_entry(&mut self, key: &LessSafeKey, entry: &Entry) -> Result<()> {
+        let mut buf = entry.write_to_bytes()?;
+        let result = self.seal(key, &buf);
+        buf.fill(0);
+        result
+    }
+
+    fn open_entry(&self, key: &LessSafeKey) -> Result<Entry> {
+        decode_entry(self.open(key)?)
+    }
+
+    /// Seals the record and its previous versions again, moving them from `old` to `new`.
+    fn reseal(&mut self, old: &LessSafeKey, new: &LessSafeKey) -> Result<()> {
+        let mut value = self.open(old)?;
+        self.seal(new, &value)?;
+        value.fill(0);
+        for version in self.history.iter_mut() {
+            let mut value = open_value(old, &self.name, &version.nonce, &version.data)?;
+            (version.nonce, version.data) = seal_value(new, &self.name, &value)?;
+            value.fill(0);
+        }
+        Ok(())
+    }
+}
+
+/// Calls `f` on every record of `dir` and of its subdirectories.
+fn for_each_record<F>(dir: &mut Directory, f: &mut F) -> Result<()>
+where
+    F: FnMut(&mut Record) -> Result<()>,
+{
+    for record in dir.records.iter_mut() {
+        f(record)?;
+    }
+    for child in dir.directories.iter_mut() {
+        for_each_record(child, f)?;
+    }
+    Ok(())
+}
+
+/// Calls `f` on every record of the vault, including the ones in the trash.
+fn for_each_body_record<F>(body: &mut Body, f: &mut F) -> Result<()>
+where
+    F: FnMut(&mut Record) -> Result<()>,
+{
+    for_each_record(body.root.mut_or_insert_default(), f)?;
+    for item in body.trash.iter_mut() {
+        match &mut item.item {
+            Some(Item::Record(record)) => f(record)?,
+            Some(Item::Directory(dir)) => for_each_record(dir, f)?,
+            None => {}
+        }
+    }
+    Ok(())
 }
 
 #[cfg(test)]
 mod test {
-    use super::{KeyGen, PasswordType, VaultManager};
-    use std::{env, fs::remove_file};
+    use super::{
+        for_each_record, trash_item_name, Aad, Entry, EntryField, KdfAlgorithm, KdfParams, KeyGen,
+        KeySlot, LocalConfig, MasterKey, Message, MessageField, Nonce, PasswordEncoding,
+        PasswordPolicy, Timestamp, VaultManager, DEFAULT_SLOT, RPDB, VERSION,
+    };
+    use crate::error::{ErrorCode, RustPassError};
+    use crate::protos::rpdb::FieldType;
+    use crate::storage::remove_with_backups;
+    use std::{env, process};
 
     #[test]
     fn test_init_save_open() {
@@ -374,11 +1241,10 @@ mod test {
         let mut vm = VaultManager::default();
         vm.regenerate(String::from(master_password)).unwrap();
 
-        let current_dir = env::current_dir().unwrap();
-        let file_path = current_dir.join("test.rpdb");
+        let file_path = env::temp_dir().join(format!("rustpass-init-{}.rpdb", process::id()));
 
         if file_path.exists() {
-            remove_file(&file_path).unwrap();
+            remove_with_backups(&file_path);
         }
 
         vm.save(&file_path).unwrap();
@@ -388,6 +1254,7 @@ mod test {
             .unwrap();
 
         assert_eq!(vm, vm1);
+        remove_with_backups(&file_path);
     }
     #[test]
     fn test_directory_add_key() {
@@ -395,9 +1262,11 @@ mod test {
         let dir_name = "test";
         let mut vm = VaultManager::default();
         vm.regenerate(String::from(master_password)).unwrap();
-        vm.add_directory(&dir_name);
-        let mut dm = vm.open_dir(&dir_name).unwrap();
+        vm.add_directory(dir_name).unwrap();
+        assert!(vm.add_directory(dir_name).is_err());
+        let mut dm = vm.open_dir(dir_name).unwrap();
         dm.add_record("aaa", "abc").unwrap();
+        assert!(dm.add_record("aaa", "abd").is_err());
 
         let buf = dm.get_record("aaa").unwrap();
         assert_eq!(buf, "abc");
@@ -407,14 +1276,687 @@ mod test {
         vm.remove_directory(dir_name).unwrap();
     }
 
+    #[test]
+    fn test_move_rename() {
+        let mut vm = VaultManager::default();
+        vm.regenerate(String::from("abcdefgh")).unwrap();
+        vm.add_directory("a").unwrap();
+        vm.add_directory("b").unwrap();
+        vm.open_dir("a")
+            .unwrap()
+            .add_record("key", "value")
+            .unwrap();
+        vm.open_dir("b").unwrap().add_record("other", "x").unwrap();
+
+        vm.move_record("a", "key", "b").unwrap();
+        assert!(vm.open_dir("a").unwrap().get_record_names().is_empty());
+        assert!(vm.move_record("a", "key", "b").is_err());
+
+        let mut dm = vm.open_dir("b").unwrap();
+        assert!(dm.rename_record("key", "other").is_err());
+        dm.rename_record("key", "renamed").unwrap();
+        assert_eq!(dm.get_record("renamed").unwrap(), "value");
+        assert!(dm.get_record("key").is_err());
+
+        assert!(vm.rename_directory("a", "b").is_err());
+        vm.rename_directory("b", "c").unwrap();
+        assert_eq!(
+            vm.open_dir("").unwrap().get_directory_names(),
+            vec!["a", "c"]
+        );
+    }
+
+    #[test]
+    fn test_entry_fields() {
+        let master_password = "abcdefgh";
+        let mut vm = VaultManager::default();
+        vm.regenerate(String::from(master_password)).unwrap();
+        let mut entry = Entry::new();
+        entry.username = "bob".into();
+        entry.password = "hunter2".into();
+        let mut dm = vm.open_dir("").unwrap();
+        dm.add_entry("mail", &entry).unwrap();
+        assert!(dm.add_entry("mail", &entry).is_err());
+
+        let created_at = dm.record("mail").unwrap().created_at.clone();
+        assert!(created_at.is_some());
+        assert!(dm.record("mail").unwrap().accessed_at.is_none());
+        dm.set_field("mail", &EntryField::Notes, "2FA enabled")
+            .unwrap();
+        dm.update_entry("mail", |entry| {
+            entry.set_custom("pin", "1234", FieldType::NUMBER, true)
+        })
+        .unwrap();
+        dm.set_expiry("mail", Some(Timestamp::now())).unwrap();
+        dm.rename_record("mail", "email").unwrap();
+
+        let file_path = env::temp_dir().join(format!("rustpass-entry-{}.rpdb", process::id()));
+        vm.save(&file_path).unwrap();
+        let mut vm1 = VaultManager::default();
+        vm1.initialize_from_file(&file_path, String::from(master_password))
+            .unwrap();
+        let mut dm = vm1.open_dir("").unwrap();
+        assert_eq!(dm.read_entry("email").unwrap().username, "bob");
+        assert!(dm.record("email").unwrap().accessed_at.is_none());
+        let entry = dm.get_entry("email").unwrap();
+        assert_eq!(entry.username, "bob");
+        assert_eq!(entry.notes, "2FA enabled");
+        assert_eq!(
+            dm.get_field("email", &EntryField::Custom("pin".into()))
+                .unwrap(),
+            Some("1234".into())
+        );
+        assert_eq!(dm.get_record("email").unwrap(), "hunter2");
+        let record = dm.record("email").unwrap();
+        assert_eq!(record.created_at, created_at);
+        assert!(record.accessed_at.is_some());
+        assert!(record.expires_at.is_some());
+        remove_with_backups(&file_path);
+    }
+
+    #[test]
+    fn test_history_depth() {
+        let mut vm = VaultManager::default();
+        vm.config.history = 2;
+        vm.regenerate(String::from("abcdefgh")).unwrap();
+        let mut dm = vm.open_dir("").unwrap();
+        dm.add_record("key", "v0").unwrap();
+        for value in ["v1", "v2", "v3"] {
+            dm.set_field("key", &EntryField::Password, value).unwrap();
+        }
+        dm.rename_record("key", "renamed").unwrap();
+
+        let history = dm.get_history("renamed").unwrap();
+        let passwords: Vec<&str> = history.iter().map(|e| e.password.as_str()).collect();
+        assert_eq!(passwords, vec!["v2", "v1"]);
+        assert!(dm.restore_version("renamed", 2).is_err());
+        dm.restore_version("renamed", 1).unwrap();
+        assert_eq!(dm.get_record("renamed").unwrap(), "v1");
+
+        vm.config.history = 0;
+        let mut dm = vm.open_dir("").unwrap();
+        dm.set_field("renamed", &EntryField::Password, "v4")
+            .unwrap();
+        assert!(dm.get_history("renamed").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_trash() {
+        let mut vm = VaultManager::default();
+        vm.regenerate(String::from("abcdefgh")).unwrap();
+        vm.add_directory("a/").unwrap();
+        vm.add_directory("a/b").unwrap();
+        vm.open_dir("a/b")
+            .unwrap()
+            .add_record("key", "value")
+            .unwrap();
+
+        vm.remove_record("a/b", "key").unwrap();
+        assert!(vm.remove_record("a/b", "key").is_err());
+        vm.remove_directory("a").unwrap();
+        assert_eq!(vm.trash().len(), 2);
+        assert_eq!(vm.trash()[0].path, "a/b");
+        assert_eq!(trash_item_name(&vm.trash()[1]), "a/");
+
+        // Restoring the record first recreates the directories it was in
+        vm.restore_from_trash(0).unwrap();
+        assert_eq!(
+            vm.open_dir("a/b").unwrap().get_record("key").unwrap(),
+            "value"
+        );
+        assert!(vm.restore_from_trash(0).is_err());
+        vm.remove_directory("a").unwrap();
+        vm.restore_from_trash(0).unwrap();
+        assert!(vm.open_dir("a/b").unwrap().get_record_names().is_empty());
+        assert!(vm.restore_from_trash(5).is_err());
+
+        vm.remove_directory("a").unwrap();
+        vm.body.trash[0].deleted_at.mut_or_insert_default().seconds -= 31 * 86400;
+        vm.purge_trash();
+        assert_eq!(vm.trash().len(), 1);
+        vm.config.trash_retention = 0;
+        vm.body.trash[0].deleted_at.mut_or_insert_default().seconds -= 365 * 86400;
+        vm.purge_trash();
+        assert_eq!(vm.trash().len(), 1);
+        vm.empty_trash();
+        assert!(vm.trash().is_empty());
+    }
+
+    #[test]
+    fn test_change_master_password() {
+        let mut vm = VaultManager::default();
+        vm.regenerate(String::from("old password")).unwrap();
+        vm.add_directory("a").unwrap();
+        let mut dm = vm.open_dir("a").unwrap();
+        dm.add_record("key", "v1").unwrap();
+        dm.set_field("key", &EntryField::Password, "v2").unwrap();
+        dm.add_record("trashed", "gone").unwrap();
+        vm.remove_record("a", "trashed").unwrap();
+        vm.add_directory("a/b").unwrap();
+        vm.open_dir("a/b").unwrap().add_record("deep", "x").unwrap();
+        vm.remove_directory("a/b").unwrap();
+
+        let (argon_salt, body_salt) = (vm.slots()[0].argon_salt.clone(), vm.body.salt.clone());
+        assert!(vm.verify_master_password(String::from("wrong")).is_err());
+        vm.verify_master_password(String::from("old password"))
+            .unwrap();
+        vm.change_master_password(String::from("new password"))
+            .unwrap();
+        assert_ne!(vm.slots()[0].argon_salt, argon_salt);
+        assert_ne!(vm.body.salt, body_salt);
+
+        let file_path = env::temp_dir().join(format!("rustpass-passwd-{}.rpdb", process::id()));
+        vm.save(&file_path).unwrap();
+        let mut vm1 = VaultManager::default();
+        assert!(vm1
+            .initialize_from_file(&file_path, String::from("old password"))
+            .is_err());
+        vm1.initialize_from_file(&file_path, String::from("new password"))
+            .unwrap();
+        let mut dm = vm1.open_dir("a").unwrap();
+        assert_eq!(dm.get_record("key").unwrap(), "v2");
+        assert_eq!(dm.get_history("key").unwrap()[0].password, "v1");
+        vm1.restore_from_trash(1).unwrap();
+        vm1.restore_from_trash(0).unwrap();
+        assert_eq!(
+            vm1.open_dir("a").unwrap().get_record("trashed").unwrap(),
+            "gone"
+        );
+        assert_eq!(
+            vm1.open_dir("a/b").unwrap().get_record("deep").unwrap(),
+            "x"
+        );
+        remove_with_backups(&file_path);
+    }
+
+    #[test]
+    fn test_key_file() {
+        let file_path = env::temp_dir().join(format!("rustpass-key-file-{}.rpdb", process::id()));
+        let open = |master_key: MasterKey| {
+            let mut vm = VaultManager::default();
+            vm.initialize_from_file(&file_path, master_key).map(|_| vm)
+        };
+        let mut vm = VaultManager::default();
+        vm.regenerate(MasterKey::new(String::from("abc"), Some([1; 32])))
+            .unwrap();
+        vm.open_dir("").unwrap().add_record("key", "value").unwrap();
+        vm.save(&file_path).unwrap();
+
+        let err = open(String::from("abc").into()).unwrap_err();
+        assert_eq!(err.to_string(), "This vault requires a key file");
+        assert_eq!(ErrorCode::of(&err), ErrorCode::WrongPassword);
+        assert!(open(MasterKey::new(String::from("abc"), Some([2; 32]))).is_err());
+        let mut vm = open(MasterKey::new(String::from("abc"), Some([1; 32]))).unwrap();
+        assert_eq!(vm.open_dir("").unwrap().get_record("key").unwrap(), "value");
+        vm.verify_master_password(String::from("abc")).unwrap();
+
+        vm.change_master_password(String::from("def")).unwrap();
+        vm.save(&file_path).unwrap();
+        let err = open(MasterKey::new(String::from("def"), Some([1; 32]))).unwrap_err();
+        assert_eq!(err.to_string(), "This vault does not use a key file");
+        let mut vm = open(String::from("def").into()).unwrap();
+        assert_eq!(vm.open_dir("").unwrap().get_record("key").unwrap(), "value");
+        remove_with_backups(&file_path);
+    }
+
+    #[test]
+    fn test_key_slots() {
+        let file_path = env::temp_dir().join(format!("rustpass-slots-{}.rpdb", process::id()));
+        let open = |master_key: MasterKey| {
+            let mut vm = VaultManager::default();
+            vm.initialize_from_file(&file_path, master_key).map(|_| vm)
+        };
+        let mut vm = VaultManager::default();
+        vm.regenerate(String::from("alice")).unwrap();
+        vm.open_dir("").unwrap().add_record("key", "value").unwrap();
+        vm.add_slot("bob", MasterKey::new(String::from("bob"), Some([1; 32])))
+            .unwrap();
+        vm.add_slot("carol", String::from("carol")).unwrap();
+        assert!(vm.add_slot("bob", String::from("other")).is_err());
+        assert!(vm.add_slot("", String::from("other")).is_err());
+        vm.save(&file_path).unwrap();
+
+        let mut vm = open(MasterKey::new(String::from("bob"), Some([1; 32]))).unwrap();
+        assert_eq!(vm.slot_index(), 1);
+        assert_eq!(vm.open_dir("").unwrap().get_record("key").unwrap(), "value");
+        let mut vm = open(String::from("carol").into()).unwrap();
+        assert_eq!(vm.slot_index(), 2);
+        assert!(vm.revoke_slot(2).is_err());
+        assert!(vm.revoke_slot(3).is_err());
+        assert_eq!(vm.revoke_slot(0).unwrap().name, DEFAULT_SLOT);
+        assert_eq!(vm.slot_index(), 1);
+
+        // With several slots, only the one the vault was unlocked with changes.
+        let data_key = vm.data_key;
+        vm.change_master_password(String::from("carol2")).unwrap();
+        assert_eq!(vm.data_key, data_key);
+        vm.save(&file_path).unwrap();
+        assert!(open(String::from("alice").into()).is_err());
+        assert!(open(String::from("carol").into()).is_err());
+        let mut vm = open(String::from("carol2").into()).unwrap();
+        assert_eq!(vm.open_dir("").unwrap().get_record("key").unwrap(), "value");
+        let mut vm = open(MasterKey::new(String::from("bob"), Some([1; 32]))).unwrap();
+        assert_eq!(vm.open_dir("").unwrap().get_record("key").unwrap(), "value");
+        remove_with_backups(&file_path);
+    }
+
+    #[test]
+    fn test_line_encoded_slot() {
+        let mut vm = VaultManager::default();
+        vm.regenerate(String::from("abcdefgh")).unwrap();
+        // Slots before 0x0009 were sealed with the line ending read from the terminal.
+        vm.header.key_slots = vec![KeySlot::wrap(
+            DEFAULT_SLOT,
+            &String::from("abcdefgh").into(),
+            &vm.data_key,
+            KdfParams::from(&vm.config),
+            PasswordEncoding::LINE,
+        )
+        .unwrap()];
+        vm.header.version = 0x0008;
+
+        let file_path = env::temp_dir().join(format!("rustpass-line-{}.rpdb", process::id()));
+        vm.save(&file_path).unwrap();
+        let mut vm1 = VaultManager::default();
+        vm1.initialize_from_file(&file_path, String::from("abcdefgh"))
+            .unwrap();
+        assert_eq!(vm1.header.version, VERSION);
+        // Changing the password writes the slot in the current encoding.
+        vm1.change_master_password(String::from("abcdefgh"))
+            .unwrap();
+        assert_eq!(
+            vm1.slots()[0].password_encoding.enum_value(),
+            Ok(PasswordEncoding::NFC)
+        );
+        remove_with_backups(&file_path);
+    }
+
+    #[test]
+    fn test_convert_to_slots() {
+        let mut vm = VaultManager::default();
+        vm.regenerate(String::from("abcdefgh")).unwrap();
+        // Before 0x0008, the data key was the hash of the only master key, as read from the
+        // terminal along with its line ending.
+        let params = KdfParams::from(&LocalConfig::default());
+        vm.header.key_slots.clear();
+        vm.header.argon_salt = vec![3; 32];
+        vm.data_key = KeyGen::hash_master_key(
+            &String::from("abcdefgh\n").into(),
+            &vm.header.argon_salt,
+            Some(&params),
+        )
+        .unwrap();
+        vm.header.kdf_params = MessageField::some(params);
+        vm.header.version = 0x0007;
+        vm.open_dir("").unwrap().add_record("key", "value").unwrap();
+
+        let file_path = env::temp_dir().join(format!("rustpass-convert-{}.rpdb", process::id()));
+        vm.save(&file_path).unwrap();
+        let mut vm1 = VaultManager::default();
+        // Without key slots, nothing tells a wrong password from a corrupted header.
+        let err = vm1
+            .initialize_from_file(&file_path, String::from("abcdefgi"))
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(RustPassError::WrongPassword)
+        ));
+        vm1.initialize_from_file(&file_path, String::from("abcdefgh"))
+            .unwrap();
+        assert_eq!(vm1.data_key, vm.data_key);
+        assert_eq!(vm1.slots().len(), 1);
+        assert!(vm1.header.argon_salt.is_empty());
+        assert!(vm1.header.kdf_params.is_none());
+
+        vm1.save(&file_path).unwrap();
+        let mut vm2 = VaultManager::default();
+        vm2.initialize_from_file(&file_path, String::from("abcdefgh"))
+            .unwrap();
+        assert_eq!(
+            vm2.open_dir("").unwrap().get_record("key").unwrap(),
+            "value"
+        );
+        remove_with_backups(&file_path);
+    }
+
+    #[test]
+    fn test_nested_directories() {
+        let mut vm = VaultManager::default();
+        vm.regenerate(String::from("abcdefgh")).unwrap();
+        vm.add_directory("infra").unwrap();
+        vm.add_directory("infra/prod").unwrap();
+        vm.add_directory("/infra/prod/db/").unwrap();
+        assert!(vm.add_directory("infra/staging/db").is_err());
+
+        vm.open_dir("infra/prod/db")
+            .unwrap()
+            .add_record("password", "abc")
+            .unwrap();
+        vm.move_record("infra/prod/db", "password", "infra")
+            .unwrap();
+        assert_eq!(
+            vm.open_dir("infra")
+                .unwrap()
+                .get_record("password")
+                .unwrap(),
+            "abc"
+        );
+        assert_eq!(
+            vm.open_dir("infra").unwrap().get_directory_names(),
+            vec!["prod"]
+        );
+
+        vm.remove_directory("infra/prod").unwrap();
+        assert!(vm.open_dir("infra/prod/db").is_err());
+        assert!(vm.remove_directory("").is_err());
+    }
+
+    /// Seals every record the way versions before 0x0006 did, with the bare password as data.
+    fn seal_bare_passwords(vm: &mut VaultManager) {
+        let key = KeyGen::derive_key(&vm.data_key, &vm.body.salt).unwrap();
+        for_each_record(vm.body.root.mut_or_insert_default(), &mut |record| {
+            let password = record.open_entry(&key)?.password;
+            record.seal(&key, password.as_bytes())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_migrate_flat_vault() {
+        let master_password = "abcdefgh";
+        let mut vm = VaultManager::default();
+        vm.regenerate(String::from(master_password)).unwrap();
+        vm.add_directory("email").unwrap();
+        vm.open_dir("email").unwrap().add_record("a", "b").unwrap();
+        seal_bare_passwords(&mut vm);
+        // Lay the vault out the way versions before 0x0002 did.
+        let directories = std::mem::take(&mut vm.body.root.mut_or_insert_default().directories);
+        vm.body.directories = directories;
+        vm.header.version = 0x0001;
+
+        let file_path = env::temp_dir().join(format!("rustpass-flat-{}.rpdb", process::id()));
+        vm.save(&file_path).unwrap();
+
+        let mut vm1 = VaultManager::default();
+        vm1.initialize_from_file(&file_path, String::from(master_password))
+            .unwrap();
+        assert!(vm1.body.directories.is_empty());
+        assert_eq!(vm1.header.version, VERSION);
+        let mut dm = vm1.open_dir("email").unwrap();
+        assert_eq!(dm.get_record("a").unwrap(), "b");
+        assert_eq!(dm.record("a").unwrap().created_at, vm.body.last_modified);
+        remove_with_backups(&file_path);
+    }
+
+    #[test]
+    fn test_record_nonces_are_unique() {
+        let mut vm = VaultManager::default();
+        vm.regenerate(String::from("abcdefgh")).unwrap();
+        for dir in ["a", "b"] {
+            vm.add_directory(dir).unwrap();
+            let mut dm = vm.open_dir(dir).unwrap();
+            dm.add_record("first", "1").unwrap();
+            dm.add_record("second", "2").unwrap();
+            dm.remove_record("second").unwrap();
+            dm.add_record("second", "2").unwrap();
+        }
+
+        let mut nonces = vec![];
+        for_each_record(vm.body.root.mut_or_insert_default(), &mut |record| {
+            nonces.push(record.nonce.clone());
+            Ok(())
+        })
+        .unwrap();
+        nonces.sort();
+        nonces.dedup();
+        assert_eq!(nonces.len(), 4);
+    }
+
+    #[test]
+    fn test_migrate_invalid_password() {
+        let master_password = "abcdefgh";
+        let mut vm = VaultManager::default();
+        vm.regenerate(String::from(master_password)).unwrap();
+        vm.open_dir("").unwrap().add_record("key", "value").unwrap();
+
+        // Versions before 0x0006 sealed the bare password, which has to be valid UTF-8.
+        let key = KeyGen::derive_key(&vm.data_key, &vm.body.salt).unwrap();
+        for_each_record(vm.body.root.mut_or_insert_default(), &mut |record| {
+            record.seal(&key, &[0xff, 0xfe])
+        })
+        .unwrap();
+        vm.header.version = 0x0005;
+        let file_path = env::temp_dir().join(format!("rustpass-utf8-{}.rpdb", process::id()));
+        vm.save(&file_path).unwrap();
+
+        let mut vm1 = VaultManager::default();
+        let err = vm1
+            .initialize_from_file(&file_path, String::from(master_password))
+            .unwrap_err();
+        assert_eq!(ErrorCode::of(&err), ErrorCode::Corrupted);
+        remove_with_backups(&file_path);
+    }
+
+    #[test]
+    fn test_migrate_record_nonces() {
+        let master_password = "abcdefgh";
+        let mut vm = VaultManager::default();
+        vm.regenerate(String::from(master_password)).unwrap();
+        vm.add_directory("a").unwrap();
+        vm.add_directory("b").unwrap();
+        vm.open_dir("a")
+            .unwrap()
+            .add_record("key", "first")
+            .unwrap();
+        vm.open_dir("b")
+            .unwrap()
+            .add_record("key", "second")
+            .unwrap();
+
+        // Seal both records under one nonce, as versions before 0x0005 did for records at the
+        // same position.
+        let key = KeyGen::derive_key(&vm.data_key, &vm.body.salt).unwrap();
+        let nonce = [9; 12];
+        for_each_record(vm.body.root.mut_or_insert_default(), &mut |record| {
+            let mut buf = record.open_entry(&key)?.password.into_bytes();
+            key.seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(record.name.as_str()),
+                &mut buf,
+            )
+            .unwrap();
+            record.nonce = nonce.to_vec();
+            record.data = buf;
+            Ok(())
+        })
+        .unwrap();
+        vm.header.version = 0x0004;
+        let file_path = env::temp_dir().join(format!("rustpass-nonces-{}.rpdb", process::id()));
+        vm.save(&file_path).unwrap();
+
+        let mut vm1 = VaultManager::default();
+        vm1.initialize_from_file(&file_path, String::from(master_password))
+            .unwrap();
+        let mut nonces = vec![];
+        for_each_record(vm1.body.root.mut_or_insert_default(), &mut |record| {
+            nonces.push(record.nonce.clone());
+            Ok(())
+        })
+        .unwrap();
+        assert!(!nonces.contains(&nonce.to_vec()));
+        assert_ne!(nonces[0], nonces[1]);
+        assert_eq!(
+            vm1.open_dir("a").unwrap().get_record("key").unwrap(),
+            "first"
+        );
+        assert_eq!(
+            vm1.open_dir("b").unwrap().get_record("key").unwrap(),
+            "second"
+        );
+        remove_with_backups(&file_path);
+    }
+
+    #[test]
+    fn test_fresh_nonce_on_save() {
+        let master_password = "abcdefgh";
+        let mut vm = VaultManager::default();
+        vm.regenerate(String::from(master_password)).unwrap();
+        let file_path = env::temp_dir().join(format!("rustpass-resave-{}.rpdb", process::id()));
+
+        let mut seen = vec![];
+        for dir in ["a", "b", "c"] {
+            vm.add_directory(dir).unwrap();
+            vm.save(&file_path).unwrap();
+            let mut vm1 = VaultManager::default();
+            vm1.initialize_from_file(&file_path, String::from(master_password))
+                .unwrap();
+            assert_eq!(vm, vm1);
+            seen.push((vm1.header.master_nonce, vm1.header.master_salt));
+        }
+        assert_ne!(seen[0], seen[1]);
+        assert_ne!(seen[1], seen[2]);
+        assert_ne!(seen[0], seen[2]);
+        remove_with_backups(&file_path);
+    }
+
+    #[test]
+    fn test_open_errors() {
+        let mut vm = VaultManager::default();
+        vm.regenerate(String::from("abcdefgh")).unwrap();
+        vm.add_directory("dir").unwrap();
+        let file_path = env::temp_dir().join(format!("rustpass-errors-{}.rpdb", process::id()));
+        vm.save(&file_path).unwrap();
+
+        let err = VaultManager::default()
+            .initialize_from_file(&file_path, String::from("abcdefgi"))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Wrong master password");
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(RustPassError::WrongPassword)
+        ));
+
+        let buf = std::fs::read(&file_path).unwrap();
+        std::fs::write(&file_path, &buf[..buf.len() - 10]).unwrap();
+        let err = VaultManager::default()
+            .initialize_from_file(&file_path, String::from("abcdefgh"))
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Vault is truncated at block"));
+        remove_with_backups(&file_path);
+    }
+
+    #[test]
+    fn test_open_legacy_format() {
+        let master_password = "abcdefgh";
+        let mut vm = VaultManager::default();
+        vm.regenerate(String::from(master_password)).unwrap();
+        vm.add_directory("dir").unwrap();
+        vm.header.version = 0x0003;
+
+        // Vaults before 0x0004 were a single protobuf with the body sealed as one blob.
+        let key = KeyGen::derive_key(&vm.data_key, &vm.header.master_salt).unwrap();
+        let nonce =
+            Nonce::assume_unique_for_key(vm.header.master_nonce.as_slice().try_into().unwrap());
+        let mut rpdb = RPDB::new();
+        rpdb.body = vm.body.write_to_bytes().unwrap();
+        key.seal_in_place_append_tag(
+            nonce,
+            Aad::from(vm.header.write_to_bytes().unwrap()),
+            &mut rpdb.body,
+        )
+        .unwrap();
+        rpdb.header = MessageField::some(vm.header.clone());
+        let file_path = env::temp_dir().join(format!("rustpass-legacy-{}.rpdb", process::id()));
+        std::fs::write(&file_path, rpdb.write_to_bytes().unwrap()).unwrap();
+
+        let mut vm1 = VaultManager::default();
+        vm1.initialize_from_file(&file_path, String::from(master_password))
+            .unwrap();
+        assert_eq!(vm1.open_dir("").unwrap().get_directory_names(), vec!["dir"]);
+
+        // Saving writes the block format, which opens like any other vault.
+        vm1.save(&file_path).unwrap();
+        let mut vm2 = VaultManager::default();
+        vm2.initialize_from_file(&file_path, String::from(master_password))
+            .unwrap();
+        assert_eq!(vm1, vm2);
+        remove_with_backups(&file_path);
+    }
+
+    #[test]
+    fn test_kdf_params_from_config() {
+        let master_password = "abcdefgh";
+        let config = LocalConfig {
+            iterations: 1,
+            memory: 1,
+            parallelism: 2,
+            ..LocalConfig::default()
+        };
+        let mut vm = VaultManager::new(config);
+        vm.regenerate(String::from(master_password)).unwrap();
+        let params = vm.slots()[0].kdf_params.clone().unwrap();
+        assert_eq!(
+            params.algorithm.enum_value_or_default(),
+            KdfAlgorithm::ARGON2ID
+        );
+        assert_eq!(
+            (params.iterations, params.memory, params.parallelism),
+            (1, 1024, 2)
+        );
+
+        let file_path = env::temp_dir().join(format!("rustpass-kdf-{}.rpdb", process::id()));
+        vm.save(&file_path).unwrap();
+        let mut vm1 = VaultManager::new(config);
+        vm1.initialize_from_file(&file_path, String::from(master_password))
+            .unwrap();
+        assert_eq!(vm, vm1);
+
+        // A vault opened with a different local configuration still uses its own parameters.
+        let mut vm2 = VaultManager::default();
+        vm2.initialize_from_file(&file_path, String::from(master_password))
+            .unwrap();
+        assert_eq!(vm.data_key, vm2.data_key);
+        remove_with_backups(&file_path);
+    }
+
+    #[test]
+    fn test_legacy_kdf_defaults() {
+        let salt = [7; 32];
+        let master_key = MasterKey::from(String::from("abc"));
+        let legacy = KeyGen::hash_master_key(&master_key, &salt, None).unwrap();
+        let mut params = KdfParams::from(&LocalConfig::default());
+        assert_ne!(
+            KeyGen::hash_master_key(&master_key, &salt, Some(&params)).unwrap(),
+            legacy
+        );
+        params.memory = 19 * 1024;
+        assert_eq!(
+            KeyGen::hash_master_key(&master_key, &salt, Some(&params)).unwrap(),
+            legacy
+        );
+        params.parallelism = 0;
+        assert!(KeyGen::hash_master_key(&master_key, &salt, Some(&params)).is_err());
+    }
+
     #[test]
     fn test_pwdgen() {
-        let key1 = KeyGen::generate_password(10, PasswordType::Alpha).unwrap();
+        let policy = |classes: &[&str]| PasswordPolicy {
+            length: 10,
+            classes: classes
+                .iter()
+                .map(|class| class.chars().collect())
+                .collect(),
+            require_each: true,
+        };
+        let key1 = KeyGen::generate_password(&policy(&["abc", "XYZ"])).unwrap();
+        assert_eq!(key1.len(), 10);
         assert!(key1.chars().all(char::is_alphabetic));
-        let key2 = KeyGen::generate_password(10, PasswordType::AlphaNum).unwrap();
-        assert!(key2.chars().all(char::is_alphanumeric));
-        let key3 = KeyGen::generate_password(10, PasswordType::All).unwrap();
-        let key4 = KeyGen::generate_password(10, PasswordType::All).unwrap();
+        assert!(key1.chars().any(char::is_uppercase));
+        let key2 = KeyGen::generate_password(&policy(&["a", "1"])).unwrap();
+        assert!(key2.contains('a') && key2.contains('1'));
+        let all = policy(&["abcdefghijklmnopqrstuvwxyz", "0123456789", "!#%$@"]);
+        let key3 = KeyGen::generate_password(&all).unwrap();
+        let key4 = KeyGen::generate_password(&all).unwrap();
         assert_ne!(key3, key4);
     }
 }
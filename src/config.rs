@@ -5,19 +5,47 @@ use std::{
     path::PathBuf,
 };
 
-use crate::commands::Executable;
+use crate::blocks::MAX_BLOCK_LEN;
+use crate::commands::{Executable, KeyGen};
+use crate::error::RustPassError;
 use crate::protos::config::Config;
-use anyhow::anyhow;
-use protobuf::Message;
+use crate::protos::rpdb::{KdfAlgorithm, KdfParams, PasswordEncoding};
+use protobuf::{EnumOrUnknown, Message};
 
-pub struct ConfigCommand;
+static CONFIG_FILE_NAME: &str = "config.txt";
+static DEFAULT_BACKUPS: u32 = 3;
+static DEFAULT_HISTORY: u32 = 10;
+static DEFAULT_TRASH_RETENTION: u32 = 30;
+
+pub struct ConfigCommand {
+    chunk_size: Option<u32>,
+    iterations: Option<u32>,
+    memory: Option<u32>,
+    parallelism: Option<u32>,
+    backups: Option<u32>,
+    history: Option<u32>,
+    trash_retention: Option<u32>,
+    unicode_normalization: Option<bool>,
+}
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct LocalConfig {
+    /// Size of the blocks vaults are written in, in KiB
     pub chunk_size: u32,
+    /// Argon2 passes used for new vaults
     pub iterations: u32,
+    /// Argon2 memory cost used for new vaults, in MiB
     pub memory: u32,
+    /// Argon2 lanes used for new vaults
     pub parallelism: u32,
+    /// Number of previous versions kept next to a vault when it is saved
+    pub backups: u32,
+    /// Number of previous versions kept in each record
+    pub history: u32,
+    /// Days deleted items are kept in the trash, 0 to keep them until it is emptied
+    pub trash_retention: u32,
+    /// Whether new master passwords are normalized to Unicode NFC before being hashed
+    pub unicode_normalization: bool,
 }
 
 impl From<LocalConfig> for Config {
@@ -27,7 +55,11 @@ impl From<LocalConfig> for Config {
         config.iterations = value.iterations;
         config.memory = value.memory;
         config.parallelism = value.parallelism;
-        return config;
+        config.backups = Some(value.backups);
+        config.history = Some(value.history);
+        config.trash_retention = Some(value.trash_retention);
+        config.unicode_normalization = Some(value.unicode_normalization);
+        config
     }
 }
 
@@ -38,6 +70,31 @@ impl From<Config> for LocalConfig {
             iterations: value.iterations,
             memory: value.memory,
             parallelism: value.parallelism,
+            backups: value.backups.unwrap_or(DEFAULT_BACKUPS),
+            history: value.history.unwrap_or(DEFAULT_HISTORY),
+            trash_retention: value.trash_retention.unwrap_or(DEFAULT_TRASH_RETENTION),
+            unicode_normalization: value.unicode_normalization.unwrap_or(true),
+        }
+    }
+}
+
+impl From<&LocalConfig> for KdfParams {
+    fn from(value: &LocalConfig) -> Self {
+        let mut params = Self::new();
+        params.algorithm = EnumOrUnknown::new(KdfAlgorithm::ARGON2ID);
+        params.iterations = value.iterations;
+        params.memory = value.memory.saturating_mul(1024);
+        params.parallelism = value.parallelism;
+        params
+    }
+}
+
+/// Encoding of the master password of new key slots.
+impl From<&LocalConfig> for PasswordEncoding {
+    fn from(value: &LocalConfig) -> Self {
+        match value.unicode_normalization {
+            true => Self::NFC,
+            false => Self::TRIMMED,
         }
     }
 }
@@ -49,6 +106,10 @@ impl Default for LocalConfig {
             iterations: 2,
             memory: 16,
             parallelism: 1,
+            backups: DEFAULT_BACKUPS,
+            history: DEFAULT_HISTORY,
+            trash_retention: DEFAULT_TRASH_RETENTION,
+            unicode_normalization: true,
         }
     }
 }
@@ -63,50 +124,89 @@ impl Config {
         ]
         .iter()
         .all(|&value| value != 0)
-        .then(|| ())
+        .then_some(())
     }
 }
 
+/// Rejects chunk sizes, in KiB, whose blocks would be too long to be written.
+fn check_chunk_size(chunk_size: u32) -> anyhow::Result<()> {
+    if chunk_size as u64 * 1024 > MAX_BLOCK_LEN as u64 {
+        return Err(RustPassError::InvalidInput(format!(
+            "Chunk size cannot be more than {} KiB",
+            MAX_BLOCK_LEN / 1024
+        ))
+        .into());
+    }
+    Ok(())
+}
+
 impl LocalConfig {
     pub fn new() -> Self {
         Self::default()
     }
 
+    /// Reads the saved configuration, or the defaults if none was saved yet.
+    pub fn load() -> anyhow::Result<Self> {
+        let mut config = Self::new();
+        if Self::get_config_location().join(CONFIG_FILE_NAME).exists() {
+            config.init_from_file()?;
+        }
+        Ok(config)
+    }
+
     pub fn init_from_file(&mut self) -> anyhow::Result<()> {
-        let config_dir = Self::get_config_location();
-        let mut config_file = Self::get_config_file(&config_dir)?;
+        self.init_from_dir(&Self::get_config_location())
+    }
+
+    fn init_from_dir(&mut self, config_dir: &PathBuf) -> anyhow::Result<()> {
+        let mut config_file = Self::get_config_file(config_dir)?;
         let mut buf: Vec<u8> = vec![];
         config_file.read_to_end(&mut buf)?;
-        let config = Config::parse_from_bytes(&buf)?;
-        config.validate().ok_or(anyhow!("Invalid configuration"))?;
+        let config = Config::parse_from_bytes(&buf)
+            .map_err(|_| RustPassError::Corrupted(String::from("Invalid configuration")))?;
+        config
+            .validate()
+            .ok_or(RustPassError::Corrupted(String::from(
+                "Invalid configuration",
+            )))?;
         *self = Self::from(config);
         Ok(())
     }
 
     pub fn save(&self) -> anyhow::Result<()> {
+        self.save_to_dir(&Self::get_config_location())
+    }
+
+    fn save_to_dir(&self, config_dir: &PathBuf) -> anyhow::Result<()> {
         let config: Config = Config::from(*self);
         let buf = config.write_to_bytes()?;
-        let mut config_file = Self::get_config_file(&Self::get_config_location())?;
-        config_file
-            .write(&buf)
-            .map(|_| ())
-            .map_err(|e| anyhow::Error::from(e))
+        let mut config_file = Self::get_config_file(config_dir)?;
+        config_file.set_len(0)?;
+        Ok(config_file.write_all(&buf)?)
     }
 
     fn get_config_location() -> PathBuf {
-        #[cfg(target_os = "macos")] // TODO: Add more configs
+        #[cfg(target_os = "macos")]
         {
-            let usr = env::var("HOME").unwrap(); // TODO: could be more graceful
+            let usr = env::var("HOME").unwrap_or_default();
             PathBuf::from(usr).join("Library/Application Support/RustPass")
         }
-        //panic!("Could not retrieve config directory");
+        #[cfg(not(target_os = "macos"))]
+        {
+            env::var("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| {
+                    PathBuf::from(env::var("HOME").unwrap_or_default()).join(".config")
+                })
+                .join("rustpass")
+        }
     }
 
     fn get_config_file(config_dir_path: &PathBuf) -> anyhow::Result<File> {
         if !config_dir_path.exists() {
             create_dir_all(config_dir_path)?;
         }
-        let config_file_path = config_dir_path.join("config.txt");
+        let config_file_path = config_dir_path.join(CONFIG_FILE_NAME);
         if config_file_path.exists() {
             return Ok(File::options()
                 .read(true)
@@ -118,24 +218,101 @@ impl LocalConfig {
 }
 
 impl ConfigCommand {
-    pub fn new() -> Self {
-        ConfigCommand {}
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        chunk_size: Option<u32>,
+        iterations: Option<u32>,
+        memory: Option<u32>,
+        parallelism: Option<u32>,
+        backups: Option<u32>,
+        history: Option<u32>,
+        trash_retention: Option<u32>,
+        unicode_normalization: Option<bool>,
+    ) -> Self {
+        ConfigCommand {
+            chunk_size,
+            iterations,
+            memory,
+            parallelism,
+            backups,
+            history,
+            trash_retention,
+            unicode_normalization,
+        }
+    }
+}
+
+impl Executable for ConfigCommand {
+    fn execute(&self) -> anyhow::Result<()> {
+        let mut config = LocalConfig::load()?;
+        let updates = [
+            (&mut config.chunk_size, self.chunk_size),
+            (&mut config.iterations, self.iterations),
+            (&mut config.memory, self.memory),
+            (&mut config.parallelism, self.parallelism),
+            (&mut config.backups, self.backups),
+            (&mut config.history, self.history),
+            (&mut config.trash_retention, self.trash_retention),
+        ];
+        let mut modified = false;
+        for (field, value) in updates {
+            if let Some(value) = value {
+                *field = value;
+                modified = true;
+            }
+        }
+        if let Some(unicode_normalization) = self.unicode_normalization {
+            config.unicode_normalization = unicode_normalization;
+            modified = true;
+        }
+
+        if modified {
+            Config::from(config)
+                .validate()
+                .ok_or(RustPassError::InvalidInput(String::from(
+                    "Only backups, history and trash retention can be set to 0",
+                )))?;
+            check_chunk_size(config.chunk_size)?;
+            KeyGen::argon2(&KdfParams::from(&config))?;
+            config.save()?;
+        }
+
+        println!("chunk size:  {}", config.chunk_size);
+        println!("iterations:  {}", config.iterations);
+        println!("memory:      {} MiB", config.memory);
+        println!("parallelism: {}", config.parallelism);
+        println!("backups:     {}", config.backups);
+        println!("history:     {}", config.history);
+        println!("trash:       {} days", config.trash_retention);
+        println!("normalize:   {}", config.unicode_normalization);
+        Ok(())
     }
 }
-impl Executable for ConfigCommand {}
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::{fs, process};
 
     #[test]
     fn test_save_restore() {
+        // Written to a directory of its own, so that the configuration of the user is left alone
+        let dir = env::temp_dir().join(format!("rustpass-config-{}", process::id()));
         let mut want = LocalConfig::new();
         want.iterations = 5;
-        want.save().unwrap();
+        want.unicode_normalization = false;
+        want.save_to_dir(&dir).unwrap();
 
         let mut config = LocalConfig::new();
-        config.init_from_file().unwrap();
+        config.init_from_dir(&dir).unwrap();
         assert_eq!(config, want);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_chunk_size_limit() {
+        assert!(check_chunk_size(64 * 1024).is_ok());
+        assert!(check_chunk_size(64 * 1024 + 1).is_err());
+        assert!(check_chunk_size(u32::MAX).is_err());
     }
 }
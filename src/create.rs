@@ -1,27 +1,49 @@
 use crate::{
     commands::{Executable, VaultManager},
-    display::InputReader,
+    config::LocalConfig,
+    error::RustPassError,
+    keyfile::{self, MasterKey},
+    password::PasswordSource,
+};
+use anyhow::Result;
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
 };
-use anyhow::{anyhow, Result};
-use std::{path::PathBuf, str::FromStr};
 
 pub struct CreateCommand {
     name: String,
     dir: String,
+    keyfile: Option<String>,
+    password: PasswordSource,
 }
 
 impl CreateCommand {
-    pub fn new(name: String, dir: String) -> Self {
-        Self { name, dir }
+    pub fn new(
+        name: String,
+        dir: String,
+        keyfile: Option<String>,
+        password: PasswordSource,
+    ) -> Self {
+        Self {
+            name,
+            dir,
+            keyfile,
+            password,
+        }
     }
 }
 
 impl Executable for CreateCommand {
     fn execute(&self) -> Result<()> {
         let path = self.generate_path()?;
-        let buf = InputReader::read_password()?;
-        let mut vm = VaultManager::default();
-        vm.regenerate(buf)?;
+        let key_file = match &self.keyfile {
+            Some(path) => Some(keyfile::load_or_generate(Path::new(path))?),
+            None => None,
+        };
+        let buf = self.password.read_new()?;
+        let mut vm = VaultManager::new(LocalConfig::load()?);
+        vm.regenerate(MasterKey::new(buf, key_file))?;
         vm.save(path)?;
         println!("Vault succesfully created");
         Ok(())
@@ -32,7 +54,7 @@ impl CreateCommand {
     fn generate_path(&self) -> Result<PathBuf> {
         let mut buf = PathBuf::from_str(&self.dir)?;
         if !buf.is_dir() {
-            return Err(anyhow!("Directory does not exist."));
+            return Err(RustPassError::NotFound(String::from("Directory")).into());
         }
         buf = buf.canonicalize()?;
         buf.push(format!("{}.rpdb", &self.name));
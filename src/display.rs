@@ -1,9 +1,148 @@
 use anyhow::Result;
+use chrono::{DateTime, SecondsFormat};
+use clap::ValueEnum;
 use nix::sys::termios::{tcgetattr, tcsetattr, LocalFlags, SetArg, Termios};
-use std::io::{stdin, stdout, Write};
+use protobuf::{well_known_types::timestamp::Timestamp, MessageField};
+use serde_json::{json, Value};
+use std::io::{stderr, stdin, stdout, BufRead, IsTerminal, Write};
 
-pub fn display_error(e: anyhow::Error) {
-    println!("Error:{}", e);
+use crate::{
+    error::{ErrorCode, RustPassError},
+    protos::rpdb::{Entry, Record},
+    strength,
+};
+
+/// How the results of one-shot commands and errors are printed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    /// Text meant to be read
+    #[default]
+    Text,
+    /// A single JSON document on stdout, or on stderr for errors
+    Json,
+}
+
+/// Prints `e` on stderr, along with its code in JSON. Returns the code, so that the process can
+/// exit with its status.
+pub fn display_error(e: anyhow::Error, format: OutputFormat) -> ErrorCode {
+    let code = ErrorCode::of(&e);
+    display_error_message(code, &e.to_string(), format);
+    code
+}
+
+pub fn display_error_message(code: ErrorCode, message: &str, format: OutputFormat) {
+    match format {
+        OutputFormat::Text => eprintln!("Error:{}", message),
+        OutputFormat::Json => eprintln!(
+            "{}",
+            json!({ "error": { "code": code.as_str(), "message": message } })
+        ),
+    }
+}
+
+/// Prints a JSON document on stdout.
+pub fn print_json(value: &Value) {
+    println!("{}", value);
+}
+
+/// Formats a timestamp as a UTC date and time, or as a date alone if it falls on midnight.
+pub fn format_timestamp(timestamp: &Timestamp) -> String {
+    match DateTime::from_timestamp(timestamp.seconds, 0) {
+        Some(time) if time.timestamp() % 86400 == 0 => time.format("%Y-%m-%d").to_string(),
+        Some(time) => time.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+        None => String::from("invalid date"),
+    }
+}
+
+/// Formats a timestamp in RFC 3339, as used in JSON output.
+pub fn format_rfc3339(timestamp: &MessageField<Timestamp>) -> Value {
+    timestamp
+        .as_ref()
+        .and_then(|timestamp| DateTime::from_timestamp(timestamp.seconds, 0))
+        .map(|time| Value::from(time.to_rfc3339_opts(SecondsFormat::Secs, true)))
+        .unwrap_or(Value::Null)
+}
+
+/// Prints an entry along with the dates of its record. Passwords and protected fields are masked
+/// unless `reveal` is set.
+pub fn print_entry(name: &str, entry: &Entry, record: &Record, reveal: bool) {
+    let line = |label: &str, value: &str| println!("{:<10} {}", format!("{}:", label), value);
+    let hide = |value: &str, protected: bool| match protected && !reveal {
+        true if !value.is_empty() => "********".to_string(),
+        _ => value.to_string(),
+    };
+
+    line("name", name);
+    line("username", &entry.username);
+    line("password", &hide(&entry.password, true));
+    for url in &entry.urls {
+        line("url", url);
+    }
+    if !entry.notes.is_empty() {
+        line("notes", &entry.notes);
+    }
+    for field in &entry.fields {
+        line(&field.name, &hide(&field.value, field.protected));
+    }
+
+    let timestamps = [
+        ("created", &record.created_at),
+        ("modified", &record.modified_at),
+        ("accessed", &record.accessed_at),
+    ];
+    for (label, timestamp) in timestamps {
+        if let Some(timestamp) = timestamp.as_ref() {
+            line(label, &format_timestamp(timestamp));
+        }
+    }
+    if let Some(expires_at) = record.expires_at.as_ref() {
+        let suffix = if is_expired(record) { " (expired)" } else { "" };
+        line(
+            "expires",
+            &format!("{}{}", format_timestamp(expires_at), suffix),
+        );
+    }
+}
+
+/// JSON document of an entry, as printed by [`print_entry`]. Masked values are `null`, while
+/// empty ones are left empty as there is nothing to hide.
+pub fn entry_json(name: &str, entry: &Entry, record: &Record, reveal: bool) -> Value {
+    let hide = |value: &str, protected: bool| match protected && !reveal {
+        true if !value.is_empty() => Value::Null,
+        _ => Value::from(value),
+    };
+    let fields: Vec<Value> = entry
+        .fields
+        .iter()
+        .map(|field| {
+            json!({
+                "name": field.name,
+                "type": format!("{:?}", field.type_.enum_value_or_default()).to_lowercase(),
+                "protected": field.protected,
+                "value": hide(&field.value, field.protected),
+            })
+        })
+        .collect();
+    json!({
+        "key": name,
+        "username": entry.username,
+        "password": hide(&entry.password, true),
+        "urls": entry.urls,
+        "notes": entry.notes,
+        "fields": fields,
+        "created": format_rfc3339(&record.created_at),
+        "modified": format_rfc3339(&record.modified_at),
+        "accessed": format_rfc3339(&record.accessed_at),
+        "expires": format_rfc3339(&record.expires_at),
+        "expired": is_expired(record),
+    })
+}
+
+fn is_expired(record: &Record) -> bool {
+    record
+        .expires_at
+        .as_ref()
+        .is_some_and(|expires_at| expires_at.seconds <= Timestamp::now().seconds)
 }
 
 pub struct TerminalControl {
@@ -13,25 +152,120 @@ pub struct TerminalControl {
 pub struct InputReader;
 
 impl InputReader {
+    /// Asks for the master password on the terminal, and returns it without its line ending.
+    /// Echo is only disabled when stdin is a terminal, so a password can be piped in as well.
     pub fn read_password() -> Result<String> {
-        let term = TerminalControl::new()?;
-        term.disable_echo()?;
-        print!("Please enter a master password: ");
-        stdout().flush()?;
+        let term = TerminalControl::for_stdin()?;
+        if let Some(term) = &term {
+            term.disable_echo()?;
+        }
+        // Prompted on stderr, so that it does not end up in the output of one-shot commands.
+        eprint!("Please enter a master password: ");
+        stderr().flush()?;
 
         let mut buf = String::new();
         stdin().read_line(&mut buf)?;
-        println!();
-        Ok(buf)
+        eprintln!();
+        Ok(buf.trim_end_matches(['\n', '\r']).to_string())
     }
 
-    pub fn read_command() -> Result<String> {
-        print!("> ");
+    /// Reads a single command line from `input`, returning `None` once the input is exhausted.
+    pub fn read_command<R: BufRead>(input: &mut R, location: &str) -> Result<Option<String>> {
+        print!("{}> ", location);
         stdout().flush()?;
 
         let mut buf = String::new();
-        stdin().read_line(&mut buf)?;
+        if input.read_line(&mut buf)? == 0 {
+            println!();
+            return Ok(None);
+        }
 
+        Ok(Some(buf))
+    }
+
+    /// Asks a yes/no question on `input`, defaulting to no.
+    pub fn read_confirmation<R: BufRead>(input: &mut R, prompt: &str) -> Result<bool> {
+        print!("{} [y/N] ", prompt);
+        stdout().flush()?;
+
+        let mut buf = String::new();
+        input.read_line(&mut buf)?;
+        Ok(matches!(buf.trim().to_lowercase().as_str(), "y" | "yes"))
+    }
+
+    /// Prompts for a single line of text on `input`.
+    pub fn read_line<R: BufRead>(input: &mut R, prompt: &str) -> Result<String> {
+        print!("{}", prompt);
+        stdout().flush()?;
+
+        let mut buf = String::new();
+        input.read_line(&mut buf)?;
+        Ok(buf.trim_end_matches(['\n', '\r']).to_string())
+    }
+
+    /// Prompts for a value that should not be echoed, such as the secret of a record. Echo is
+    /// only disabled when stdin is a terminal.
+    pub fn read_secret<R: BufRead>(input: &mut R, prompt: &str) -> Result<String> {
+        let buf = Self::read_hidden_line(input, prompt, &mut stdout())?;
+        Ok(buf.trim_end_matches(['\n', '\r']).to_string())
+    }
+
+    /// Like `read_secret`, but prompts on stderr, so that it does not end up in the output of
+    /// one-shot commands.
+    pub fn read_secret_on_stderr<R: BufRead>(input: &mut R, prompt: &str) -> Result<String> {
+        let buf = Self::read_hidden_line(input, prompt, &mut stderr())?;
+        Ok(buf.trim_end_matches(['\n', '\r']).to_string())
+    }
+
+    /// Reads a master password from `input` without echoing it.
+    pub fn read_master_password<R: BufRead>(input: &mut R, prompt: &str) -> Result<String> {
+        Self::read_secret(input, prompt)
+    }
+
+    /// Prompts twice for a new master password, and checks that both match.
+    pub fn read_new_master_password<R: BufRead>(input: &mut R) -> Result<String> {
+        let password = Self::read_master_password(input, "New master password: ")?;
+        if password.is_empty() {
+            return Err(RustPassError::InvalidInput(String::from(
+                "Master password cannot be empty",
+            ))
+            .into());
+        }
+        if Self::read_master_password(input, "Repeat the new master password: ")? != password {
+            return Err(RustPassError::InvalidInput(String::from("Passwords do not match")).into());
+        }
+        Self::warn_if_weak(&password);
+        Ok(password)
+    }
+
+    /// Warns when a new master password is easy to guess. It is accepted either way.
+    pub fn warn_if_weak(password: &str) {
+        let strength = strength::estimate(password);
+        if strength.is_weak() {
+            println!(
+                "Warning: this master password is {}, and could be guessed in about 10^{:.0} tries. \
+                 Consider a passphrase from `rustpass passphrase`",
+                strength.description(),
+                strength.log_guesses
+            );
+        }
+    }
+
+    fn read_hidden_line<R: BufRead, W: Write>(
+        input: &mut R,
+        prompt: &str,
+        out: &mut W,
+    ) -> Result<String> {
+        let term = TerminalControl::for_stdin()?;
+        if let Some(term) = &term {
+            term.disable_echo()?;
+        }
+        write!(out, "{}", prompt)?;
+        out.flush()?;
+
+        let mut buf = String::new();
+        input.read_line(&mut buf)?;
+        writeln!(out)?;
         Ok(buf)
     }
 }
@@ -39,10 +273,19 @@ impl InputReader {
 impl TerminalControl {
     pub fn new() -> Result<Self> {
         Ok(Self {
-            term: tcgetattr(&stdin())?,
+            term: tcgetattr(stdin())?,
         })
     }
 
+    /// Controls stdin if it is a terminal. Otherwise there is no echo to disable, and `None` is
+    /// returned rather than the error `tcgetattr` fails with.
+    pub fn for_stdin() -> Result<Option<Self>> {
+        match stdin().is_terminal() {
+            true => Self::new().map(Some),
+            false => Ok(None),
+        }
+    }
+
     pub fn disable_echo(&self) -> Result<()> {
         let in_fd = stdin();
         let mut term = tcgetattr(&in_fd)?;
@@ -51,7 +294,7 @@ impl TerminalControl {
     }
 
     fn restore(&self) -> Result<()> {
-        Ok(tcsetattr(&stdin(), SetArg::TCSANOW, &self.term)?)
+        Ok(tcsetattr(stdin(), SetArg::TCSANOW, &self.term)?)
     }
 }
 
@@ -60,3 +303,26 @@ impl Drop for TerminalControl {
         let _ = self.restore();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_json_masks_values() {
+        let mut entry = Entry::new();
+        entry.password = String::from("hunter2");
+        let json = entry_json("key", &entry, &Record::new(), false);
+        assert_eq!(json["password"], Value::Null);
+        assert_eq!(
+            entry_json("key", &entry, &Record::new(), true)["password"],
+            "hunter2"
+        );
+
+        entry.password.clear();
+        assert_eq!(
+            entry_json("key", &entry, &Record::new(), false)["password"],
+            ""
+        );
+    }
+}
@@ -0,0 +1,250 @@
+//! Fields of the entries stored in records, and how they are read and updated.
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use protobuf::{well_known_types::timestamp::Timestamp, EnumOrUnknown};
+
+use crate::{
+    error::RustPassError,
+    protos::rpdb::{Entry, Field, FieldType},
+};
+
+/// Field of an entry, as named in the REPL.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EntryField {
+    Username,
+    Password,
+    /// All the URLs of the entry, separated by whitespace
+    Urls,
+    Notes,
+    /// Custom field, by name
+    Custom(String),
+}
+
+impl EntryField {
+    pub fn parse(name: &str) -> Result<Self> {
+        Ok(match name {
+            "username" => Self::Username,
+            "password" => Self::Password,
+            "url" | "urls" => Self::Urls,
+            "notes" => Self::Notes,
+            "name" | "created" | "modified" | "accessed" | "expires" => {
+                return Err(RustPassError::InvalidInput(format!(
+                    "{} cannot be used as a custom field name",
+                    name
+                ))
+                .into())
+            }
+            "" => {
+                return Err(
+                    RustPassError::InvalidInput(String::from("Field name cannot be empty")).into(),
+                )
+            }
+            name => Self::Custom(name.to_string()),
+        })
+    }
+}
+
+pub fn parse_field_type(name: &str) -> Result<FieldType> {
+    Ok(match name {
+        "text" => FieldType::TEXT,
+        "url" => FieldType::URL,
+        "email" => FieldType::EMAIL,
+        "number" => FieldType::NUMBER,
+        "date" => FieldType::DATE,
+        _ => {
+            return Err(RustPassError::InvalidInput(String::from(
+                "Unknown field type, expected one of text, url, email, number or date",
+            ))
+            .into())
+        }
+    })
+}
+
+/// Parses a `YYYY-MM-DD` date into a timestamp at midnight UTC.
+pub fn parse_date(value: &str) -> Result<Timestamp> {
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| {
+        RustPassError::InvalidInput(String::from("Invalid date, expected YYYY-MM-DD"))
+    })?;
+    let mut timestamp = Timestamp::new();
+    timestamp.seconds = date.and_time(Default::default()).and_utc().timestamp();
+    Ok(timestamp)
+}
+
+fn validate_value(field_type: FieldType, value: &str) -> Result<()> {
+    let valid = match field_type {
+        FieldType::TEXT => true,
+        FieldType::URL => value.contains("://"),
+        FieldType::EMAIL => value
+            .split_once('@')
+            .is_some_and(|(user, domain)| !user.is_empty() && domain.contains('.')),
+        FieldType::NUMBER => value.parse::<f64>().is_ok(),
+        FieldType::DATE => parse_date(value).is_ok(),
+    };
+    if !valid {
+        return Err(RustPassError::InvalidInput(format!(
+            "{:?} is not a valid {:?} value",
+            value, field_type
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+impl Entry {
+    pub fn get(&self, field: &EntryField) -> Option<String> {
+        match field {
+            EntryField::Username => Some(self.username.clone()),
+            EntryField::Password => Some(self.password.clone()),
+            EntryField::Urls => Some(self.urls.join(" ")),
+            EntryField::Notes => Some(self.notes.clone()),
+            EntryField::Custom(name) => self.custom_field(name).map(|f| f.value.clone()),
+        }
+    }
+
+    /// Sets a field to `value`. Custom fields are created as unprotected text if they do not
+    /// exist yet, and removed when `value` is empty.
+    pub fn set(&mut self, field: &EntryField, value: &str) -> Result<()> {
+        match field {
+            EntryField::Username => self.username = value.to_string(),
+            EntryField::Password => self.password = value.to_string(),
+            EntryField::Urls => self.urls = value.split_whitespace().map(String::from).collect(),
+            EntryField::Notes => self.notes = value.to_string(),
+            EntryField::Custom(name) => {
+                let (field_type, protected) = self
+                    .custom_field(name)
+                    .map(|f| (f.type_.enum_value_or_default(), f.protected))
+                    .unwrap_or((FieldType::TEXT, false));
+                self.set_custom(name, value, field_type, protected)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets a custom field along with its type and protection, or removes it when `value` is
+    /// empty.
+    pub fn set_custom(
+        &mut self,
+        name: &str,
+        value: &str,
+        field_type: FieldType,
+        protected: bool,
+    ) -> Result<()> {
+        let index = self.fields.iter().position(|f| f.name == name);
+        if value.is_empty() {
+            if let Some(index) = index {
+                self.fields.remove(index);
+            }
+            return Ok(());
+        }
+        validate_value(field_type, value)?;
+
+        let field = match index {
+            Some(index) => &mut self.fields[index],
+            None => {
+                self.fields.push(Field::new());
+                self.fields.last_mut().unwrap()
+            }
+        };
+        field.name = name.to_string();
+        field.type_ = EnumOrUnknown::new(field_type);
+        field.value = value.to_string();
+        field.protected = protected;
+        Ok(())
+    }
+
+    pub fn custom_field(&self, name: &str) -> Option<&Field> {
+        self.fields.iter().find(|f| f.name == name)
+    }
+
+    /// Names of the fields whose value differs between `self` and `other`.
+    pub fn changed_fields(&self, other: &Entry) -> Vec<String> {
+        let mut names: Vec<String> = [
+            EntryField::Username,
+            EntryField::Password,
+            EntryField::Urls,
+            EntryField::Notes,
+        ]
+        .into_iter()
+        .zip(["username", "password", "url", "notes"])
+        .filter(|(field, _)| self.get(field) != other.get(field))
+        .map(|(_, name)| name.to_string())
+        .collect();
+
+        let custom = self.fields.iter().chain(&other.fields);
+        for field in custom {
+            let changed = self.custom_field(&field.name) != other.custom_field(&field.name);
+            if changed && !names.contains(&field.name) {
+                names.push(field.name.clone());
+            }
+        }
+        names
+    }
+
+    /// Whether the value of `field` should be hidden when displayed and typed in.
+    pub fn is_protected(&self, field: &EntryField) -> bool {
+        match field {
+            EntryField::Password => true,
+            EntryField::Custom(name) => self.custom_field(name).is_some_and(|f| f.protected),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_fields() {
+        let mut entry = Entry::new();
+        entry.set(&EntryField::Username, "bob").unwrap();
+        entry
+            .set(&EntryField::Urls, "https://a.example  https://b.example")
+            .unwrap();
+        assert_eq!(entry.urls, vec!["https://a.example", "https://b.example"]);
+        assert_eq!(entry.get(&EntryField::Username).unwrap(), "bob");
+
+        let pin = EntryField::parse("pin").unwrap();
+        assert!(entry
+            .set_custom("pin", "12a4", FieldType::NUMBER, true)
+            .is_err());
+        entry
+            .set_custom("pin", "1234", FieldType::NUMBER, true)
+            .unwrap();
+        assert!(entry.is_protected(&pin));
+        // Updating the value keeps the type and the protection
+        assert!(entry.set(&pin, "abcd").is_err());
+        entry.set(&pin, "4321").unwrap();
+        assert!(entry.is_protected(&pin));
+        assert_eq!(entry.get(&pin).unwrap(), "4321");
+
+        entry.set(&pin, "").unwrap();
+        assert!(entry.get(&pin).is_none());
+        assert!(entry.fields.is_empty());
+        assert!(EntryField::parse("expires").is_err());
+    }
+
+    #[test]
+    fn test_changed_fields() {
+        let mut old = Entry::new();
+        old.password = "a".into();
+        old.set_custom("pin", "1", FieldType::NUMBER, false)
+            .unwrap();
+        let mut new = old.clone();
+        assert!(new.changed_fields(&old).is_empty());
+
+        new.password = "b".into();
+        new.set_custom("pin", "1", FieldType::NUMBER, true).unwrap();
+        new.set_custom("code", "x", FieldType::TEXT, false).unwrap();
+        assert_eq!(new.changed_fields(&old), vec!["password", "pin", "code"]);
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("1970-01-02").unwrap().seconds, 86400);
+        assert!(parse_date("2024-13-01").is_err());
+        assert!(validate_value(FieldType::EMAIL, "bob@example.com").is_ok());
+        assert!(validate_value(FieldType::EMAIL, "bob").is_err());
+    }
+}
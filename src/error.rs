@@ -0,0 +1,168 @@
+//! Typed errors, so that a wrong master password can be told apart from a corrupted vault or a
+//! missing key. They are returned inside `anyhow::Error` like any other error, and recovered with
+//! [`ErrorCode::of`] where the kind matters, such as for exit codes and JSON errors.
+
+use std::{fmt, io};
+
+#[derive(Debug)]
+pub enum RustPassError {
+    /// No key slot opens with the master password and key file given
+    WrongPassword,
+    /// The vault requires a key file and none was given, or the other way around
+    KeyFileMismatch { required: bool },
+    /// The vault, or a key file, failed its integrity checks
+    Corrupted(String),
+    /// The vault was written by a newer version of rustpass
+    UnsupportedVersion { found: u32, supported: u32 },
+    /// Key, directory, key slot, version or trash item that does not exist, by description
+    NotFound(String),
+    /// Key, directory or key slot that already exists, by description
+    AlreadyExists(String),
+    /// The vault is locked by another session, described by its owner
+    Locked(String),
+    /// A value given on the command line or in the configuration is invalid
+    InvalidInput(String),
+    /// A file could not be read or written
+    Io { context: String, source: io::Error },
+    /// A cryptographic operation failed, such as drawing random bytes
+    Crypto(String),
+}
+
+impl fmt::Display for RustPassError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WrongPassword => write!(f, "Wrong master password"),
+            Self::KeyFileMismatch { required: true } => write!(f, "This vault requires a key file"),
+            Self::KeyFileMismatch { required: false } => {
+                write!(f, "This vault does not use a key file")
+            }
+            Self::Corrupted(message) => write!(f, "{}", message),
+            Self::UnsupportedVersion { found, supported } => write!(
+                f,
+                "Vault version {:#06x} is newer than the supported {:#06x}",
+                found, supported
+            ),
+            Self::NotFound(what) => write!(f, "{} does not exist", what),
+            Self::AlreadyExists(what) => write!(f, "{} already exists", what),
+            Self::Locked(owner) => write!(f, "The vault is locked by {}", owner),
+            Self::InvalidInput(message) => write!(f, "{}", message),
+            Self::Io { context, source } => write!(f, "{}: {}", context, source),
+            Self::Crypto(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for RustPassError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Kind of failure, as reported by the exit status and in JSON errors. Codes are part of the
+/// output format and must not change.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorCode {
+    /// The command failed for another reason
+    Failed,
+    /// The command line is invalid
+    Usage,
+    WrongPassword,
+    Corrupted,
+    UnsupportedVersion,
+    NotFound,
+    AlreadyExists,
+    Locked,
+    InvalidInput,
+    Io,
+}
+
+impl ErrorCode {
+    /// Code of `e`, from the [`RustPassError`] it holds. Other I/O errors are reported as such,
+    /// and anything else as [`Self::Failed`].
+    pub fn of(e: &anyhow::Error) -> Self {
+        if let Some(e) = e.downcast_ref::<RustPassError>() {
+            return match e {
+                RustPassError::WrongPassword | RustPassError::KeyFileMismatch { .. } => {
+                    Self::WrongPassword
+                }
+                RustPassError::Corrupted(_) => Self::Corrupted,
+                RustPassError::UnsupportedVersion { .. } => Self::UnsupportedVersion,
+                RustPassError::NotFound(_) => Self::NotFound,
+                RustPassError::AlreadyExists(_) => Self::AlreadyExists,
+                RustPassError::Locked(_) => Self::Locked,
+                RustPassError::InvalidInput(_) => Self::InvalidInput,
+                RustPassError::Io { .. } => Self::Io,
+                RustPassError::Crypto(_) => Self::Failed,
+            };
+        }
+        match e.downcast_ref::<io::Error>() {
+            Some(_) => Self::Io,
+            None => Self::Failed,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Failed => "failed",
+            Self::Usage => "usage",
+            Self::WrongPassword => "wrong_password",
+            Self::Corrupted => "corrupted",
+            Self::UnsupportedVersion => "unsupported_version",
+            Self::NotFound => "not_found",
+            Self::AlreadyExists => "already_exists",
+            Self::Locked => "locked",
+            Self::InvalidInput => "invalid_input",
+            Self::Io => "io",
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Failed => 1,
+            Self::Usage => 2,
+            Self::WrongPassword => 3,
+            Self::Corrupted => 4,
+            Self::UnsupportedVersion => 5,
+            Self::NotFound => 6,
+            Self::AlreadyExists => 7,
+            Self::Locked => 8,
+            Self::InvalidInput => 9,
+            Self::Io => 10,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_error_codes() {
+        let code = |e: RustPassError| ErrorCode::of(&e.into());
+        assert_eq!(code(RustPassError::WrongPassword), ErrorCode::WrongPassword);
+        assert_eq!(
+            code(RustPassError::KeyFileMismatch { required: true }),
+            ErrorCode::WrongPassword
+        );
+        assert_eq!(
+            code(RustPassError::NotFound(String::from("Key"))),
+            ErrorCode::NotFound
+        );
+        let io = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert_eq!(ErrorCode::of(&io.into()), ErrorCode::Io);
+        assert_eq!(ErrorCode::of(&anyhow!("Anything else")), ErrorCode::Failed);
+
+        // Context added on the way up does not hide the kind of error.
+        let e = anyhow::Error::from(RustPassError::Locked(String::from("bob@host")))
+            .context("Could not save");
+        assert_eq!(ErrorCode::of(&e), ErrorCode::Locked);
+        assert_eq!(
+            RustPassError::NotFound(String::from("Key")).to_string(),
+            "Key does not exist"
+        );
+    }
+}
@@ -0,0 +1,372 @@
+//! Random password and passphrase generation. A password policy picks the character classes a
+//! password is drawn from, and every character is drawn uniformly from their union. Passphrases
+//! are drawn word by word from a wordlist in the same way.
+
+use crate::{
+    commands::{Executable, KeyGen},
+    error::RustPassError,
+    parsing::{GenerateArgs, PassphraseArgs},
+};
+use anyhow::Result;
+use std::fs;
+
+const LOWER: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPER: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+const SYMBOLS: &str = "!#$%&()*+,-./:;<=>?@[]^_{|}~";
+/// Characters that are easily mistaken for one another
+const AMBIGUOUS: &str = "Il1|O0o";
+/// Wordlist used unless another one is given, in the format of the EFF dice wordlists: a line
+/// per word, after its dice roll
+const WORDLIST: &str = include_str!("wordlist.txt");
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PasswordPolicy {
+    pub length: usize,
+    /// Disjoint sets of characters passwords are drawn from
+    pub classes: Vec<Vec<char>>,
+    /// Whether every password holds at least one character of each class
+    pub require_each: bool,
+}
+
+impl PasswordPolicy {
+    /// Builds the policy described by `args`. Lower and upper case letters, digits and symbols
+    /// are all used when no class is selected.
+    pub fn new(args: &GenerateArgs) -> Result<Self> {
+        if args.length == 0 {
+            return Err(RustPassError::InvalidInput(String::from(
+                "Password length must be at least 1",
+            ))
+            .into());
+        }
+        let mut selected: Vec<&str> = [
+            (args.lower, LOWER),
+            (args.upper, UPPER),
+            (args.digits, DIGITS),
+            (args.symbols, SYMBOLS),
+        ]
+        .into_iter()
+        .filter_map(|(selected, class)| selected.then_some(class))
+        .collect();
+        if selected.is_empty() && args.custom.is_none() {
+            selected = vec![LOWER, UPPER, DIGITS, SYMBOLS];
+        }
+
+        let mut classes: Vec<Vec<char>> = Vec::new();
+        let custom = args.custom.as_deref().into_iter();
+        for class in selected.into_iter().chain(custom) {
+            let mut chars: Vec<char> = Vec::new();
+            for c in class.chars() {
+                let ambiguous = args.no_ambiguous && AMBIGUOUS.contains(c);
+                let seen = chars.contains(&c) || classes.iter().any(|class| class.contains(&c));
+                if !ambiguous && !seen && !c.is_control() {
+                    chars.push(c);
+                }
+            }
+            if !chars.is_empty() {
+                classes.push(chars);
+            }
+        }
+
+        if classes.is_empty() {
+            return Err(RustPassError::InvalidInput(String::from(
+                "No characters left to generate a password from",
+            ))
+            .into());
+        }
+        if args.require_each && args.length < classes.len() {
+            return Err(RustPassError::InvalidInput(format!(
+                "Password length must be at least {} to hold a character of each class",
+                classes.len()
+            ))
+            .into());
+        }
+        Ok(Self {
+            length: args.length,
+            classes,
+            require_each: args.require_each,
+        })
+    }
+
+    /// Union of the character classes.
+    pub fn charset(&self) -> Vec<char> {
+        self.classes.concat()
+    }
+
+    /// Whether `password` holds a character of each class, if the policy requires it.
+    pub fn accepts(&self, password: &str) -> bool {
+        !self.require_each
+            || self
+                .classes
+                .iter()
+                .all(|class| password.chars().any(|c| class.contains(&c)))
+    }
+
+    /// Entropy of the generated passwords, in bits.
+    ///
+    /// Passwords are uniform over the ones the policy accepts, so this is the base 2 logarithm of
+    /// their number. When every class is required, the share of accepted passwords among all
+    /// `n^length` follows from inclusion-exclusion over the classes that are left out.
+    pub fn entropy(&self) -> f64 {
+        let total = self.charset().len() as f64;
+        let bits = self.length as f64 * total.log2();
+        if !self.require_each {
+            return bits;
+        }
+        let mut accepted = 0.0;
+        for missing in 0..1u64 << self.classes.len() {
+            let size: usize = self
+                .classes
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| missing & (1 << i) != 0)
+                .map(|(_, class)| class.len())
+                .sum();
+            let share = (1.0 - size as f64 / total).powi(self.length as i32);
+            match missing.count_ones() % 2 {
+                0 => accepted += share,
+                _ => accepted -= share,
+            }
+        }
+        bits + accepted.log2()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PassphrasePolicy {
+    pub words: usize,
+    pub separator: String,
+    /// Whether every word starts with a capital letter
+    pub capitalize: bool,
+    /// Whether a random digit is appended to one of the words
+    pub digit: bool,
+    /// Distinct words passphrases are drawn from
+    pub wordlist: Vec<String>,
+}
+
+impl PassphrasePolicy {
+    /// Builds the policy described by `args`, reading the wordlist it names if any.
+    pub fn new(args: &PassphraseArgs) -> Result<Self> {
+        if args.words == 0 {
+            return Err(RustPassError::InvalidInput(String::from(
+                "Passphrase must have at least 1 word",
+            ))
+            .into());
+        }
+        let wordlist =
+            match &args.wordlist {
+                Some(path) => parse_wordlist(&fs::read_to_string(path).map_err(|source| {
+                    RustPassError::Io {
+                        context: format!("Could not read wordlist {}", path),
+                        source,
+                    }
+                })?),
+                None => parse_wordlist(WORDLIST),
+            };
+        if wordlist.len() < 2 {
+            return Err(RustPassError::InvalidInput(String::from(
+                "Wordlist must hold at least 2 distinct words",
+            ))
+            .into());
+        }
+        Ok(Self {
+            words: args.words,
+            separator: args.separator.clone(),
+            capitalize: args.capitalize,
+            digit: args.digit,
+            wordlist,
+        })
+    }
+
+    /// Entropy of the generated passphrases, in bits. The digit adds its own value and the word
+    /// it is appended to.
+    pub fn entropy(&self) -> f64 {
+        let mut bits = self.words as f64 * (self.wordlist.len() as f64).log2();
+        if self.digit {
+            bits += 10f64.log2() + (self.words as f64).log2();
+        }
+        bits
+    }
+}
+
+/// Reads the words of a wordlist, one per line. Anything before the last whitespace of a line,
+/// such as the dice rolls of the EFF wordlists, is ignored, and so are repeated words.
+fn parse_wordlist(contents: &str) -> Vec<String> {
+    let mut words: Vec<String> = Vec::new();
+    for line in contents.lines() {
+        if let Some(word) = line.split_whitespace().last() {
+            if !words.iter().any(|w| w == word) {
+                words.push(word.to_string());
+            }
+        }
+    }
+    words
+}
+
+pub fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Either kind of generated secret, as offered by `add` in the REPL.
+pub enum Generator {
+    Password(PasswordPolicy),
+    Passphrase(PassphrasePolicy),
+}
+
+impl Generator {
+    pub fn generate(&self) -> Result<String> {
+        match self {
+            Self::Password(policy) => KeyGen::generate_password(policy),
+            Self::Passphrase(policy) => KeyGen::generate_passphrase(policy),
+        }
+    }
+
+    pub fn entropy(&self) -> f64 {
+        match self {
+            Self::Password(policy) => policy.entropy(),
+            Self::Passphrase(policy) => policy.entropy(),
+        }
+    }
+}
+
+pub struct GenerateCommand {
+    args: GenerateArgs,
+}
+
+impl GenerateCommand {
+    pub fn new(args: GenerateArgs) -> Self {
+        Self { args }
+    }
+}
+
+impl Executable for GenerateCommand {
+    fn execute(&self) -> Result<()> {
+        let policy = PasswordPolicy::new(&self.args)?;
+        println!("{}", KeyGen::generate_password(&policy)?);
+        // Keep the password alone on stdout, so it can be piped elsewhere.
+        eprintln!("Entropy: {:.1} bits", policy.entropy());
+        Ok(())
+    }
+}
+
+pub struct PassphraseCommand {
+    args: PassphraseArgs,
+}
+
+impl PassphraseCommand {
+    pub fn new(args: PassphraseArgs) -> Self {
+        Self { args }
+    }
+}
+
+impl Executable for PassphraseCommand {
+    fn execute(&self) -> Result<()> {
+        let policy = PassphrasePolicy::new(&self.args)?;
+        println!("{}", KeyGen::generate_passphrase(&policy)?);
+        eprintln!("Entropy: {:.1} bits", policy.entropy());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(length: usize) -> GenerateArgs {
+        GenerateArgs {
+            length,
+            lower: false,
+            upper: false,
+            digits: false,
+            symbols: false,
+            custom: None,
+            no_ambiguous: false,
+            require_each: false,
+        }
+    }
+
+    #[test]
+    fn test_classes() {
+        let policy = PasswordPolicy::new(&args(20)).unwrap();
+        assert_eq!(policy.classes.len(), 4);
+        assert_eq!(policy.charset().len(), 26 + 26 + 10 + SYMBOLS.len());
+
+        let policy = PasswordPolicy::new(&GenerateArgs {
+            digits: true,
+            custom: Some(String::from("0ab")),
+            no_ambiguous: true,
+            ..args(20)
+        })
+        .unwrap();
+        assert_eq!(policy.classes[0], "23456789".chars().collect::<Vec<_>>());
+        assert_eq!(policy.classes[1], vec!['a', 'b']);
+
+        assert!(PasswordPolicy::new(&GenerateArgs {
+            custom: Some(String::from("1l")),
+            no_ambiguous: true,
+            ..args(20)
+        })
+        .is_err());
+        assert!(PasswordPolicy::new(&args(0)).is_err());
+        assert!(PasswordPolicy::new(&GenerateArgs {
+            require_each: true,
+            ..args(3)
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_entropy() {
+        let digits = GenerateArgs {
+            digits: true,
+            ..args(4)
+        };
+        let policy = PasswordPolicy::new(&digits).unwrap();
+        assert!((policy.entropy() - 4.0 * 10f64.log2()).abs() < 1e-9);
+
+        // Of the 4^2 passwords over two classes of two characters, 8 hold both classes.
+        let policy = PasswordPolicy {
+            length: 2,
+            classes: vec![vec!['a', 'b'], vec!['0', '1']],
+            require_each: true,
+        };
+        assert!((policy.entropy() - 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_wordlist() {
+        let wordlist = parse_wordlist(WORDLIST);
+        assert_eq!(wordlist.len(), 6 * 6 * 6 * 6);
+        assert!(wordlist
+            .iter()
+            .all(|word| word.chars().all(|c| c.is_ascii_lowercase())));
+        assert_eq!(
+            parse_wordlist("11\tone\n12 two\nthree\n\n13\tone\n"),
+            vec!["one", "two", "three"]
+        );
+    }
+
+    #[test]
+    fn test_passphrase() {
+        let policy = PassphrasePolicy {
+            words: 4,
+            separator: String::from("."),
+            capitalize: true,
+            digit: true,
+            wordlist: vec![String::from("alpha"), String::from("beta")],
+        };
+        let passphrase = KeyGen::generate_passphrase(&policy).unwrap();
+        let words: Vec<&str> = passphrase.split('.').collect();
+        assert_eq!(words.len(), 4);
+        assert!(words
+            .iter()
+            .all(|w| w.starts_with("Alpha") || w.starts_with("Beta")));
+        assert_eq!(passphrase.chars().filter(char::is_ascii_digit).count(), 1);
+        assert!((policy.entropy() - (4.0 + 10f64.log2() + 2.0)).abs() < 1e-9);
+        assert_eq!(capitalize("éclair"), "Éclair");
+    }
+}
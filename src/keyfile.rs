@@ -0,0 +1,223 @@
+//! Composite master keys, made of the master password and an optional key file.
+//!
+//! Any file can serve as a key file, in which case its SHA-256 hash is used. Key files generated
+//! by rustpass hold a random 256-bit key in hex instead, along with a checksum so that a key file
+//! which was edited by accident is rejected rather than silently producing another key.
+
+use std::{fs, io::Write, path::Path};
+
+use anyhow::Result;
+use ring::{
+    digest::{digest, SHA256},
+    rand::{SecureRandom, SystemRandom},
+};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::{commands::KeyBuffer, error::RustPassError, protos::rpdb::PasswordEncoding, storage};
+
+const KEY_FILE_HEADER: &str = "rustpass key file v1";
+
+/// Everything the master hash is derived from.
+pub struct MasterKey {
+    password: String,
+    key_file: Option<KeyBuffer>,
+}
+
+impl MasterKey {
+    pub fn new(password: String, key_file: Option<KeyBuffer>) -> Self {
+        Self { password, key_file }
+    }
+
+    pub fn key_file(&self) -> Option<&KeyBuffer> {
+        self.key_file.as_ref()
+    }
+
+    /// The master key with its password encoded the way a key slot expects. Passwords are read
+    /// without their line ending, which slots written before version 0x0009 included.
+    pub fn encode(&self, encoding: PasswordEncoding) -> Self {
+        let password = match encoding {
+            PasswordEncoding::LINE => format!("{}\n", self.password),
+            PasswordEncoding::TRIMMED => self.password.clone(),
+            PasswordEncoding::NFC => self.password.nfc().collect(),
+        };
+        Self::new(password, self.key_file)
+    }
+
+    /// Bytes fed to Argon2. A password alone is used as is, which keeps vaults created before key
+    /// files existed working. With a key file, the input is `SHA-256(SHA-256(password) ‖ key)`.
+    pub fn argon_input(&self) -> Vec<u8> {
+        let Some(key_file) = &self.key_file else {
+            return self.password.as_bytes().to_vec();
+        };
+        let mut buf = digest(&SHA256, self.password.as_bytes()).as_ref().to_vec();
+        buf.extend_from_slice(key_file);
+        let input = digest(&SHA256, &buf).as_ref().to_vec();
+        buf.fill(0);
+        input
+    }
+}
+
+impl From<String> for MasterKey {
+    fn from(password: String) -> Self {
+        Self::new(password, None)
+    }
+}
+
+impl Drop for MasterKey {
+    fn drop(&mut self) {
+        std::mem::take(&mut self.password).into_bytes().fill(0);
+        if let Some(key_file) = &mut self.key_file {
+            key_file.fill(0);
+        }
+    }
+}
+
+/// Reads the key held by a key file. Files in the generated format yield the key they contain,
+/// any other file yields the SHA-256 hash of its contents.
+pub fn load(path: &Path) -> Result<KeyBuffer> {
+    let mut contents = fs::read(path).map_err(|source| RustPassError::Io {
+        context: format!("Could not read key file {}", path.display()),
+        source,
+    })?;
+    if contents.is_empty() {
+        return Err(
+            RustPassError::InvalidInput(format!("Key file {} is empty", path.display())).into(),
+        );
+    }
+    let result = match std::str::from_utf8(&contents) {
+        Ok(text) if text.lines().next() == Some(KEY_FILE_HEADER) => parse_generated(text),
+        _ => Ok(digest(&SHA256, &contents).as_ref().try_into()?),
+    };
+    contents.fill(0);
+    result.map_err(|e| {
+        RustPassError::Corrupted(format!("Key file {} is corrupted: {}", path.display(), e)).into()
+    })
+}
+
+/// Loads the key file at `path`, if one was given.
+pub fn load_optional(path: Option<&str>) -> Result<Option<KeyBuffer>> {
+    path.map(|path| load(Path::new(path))).transpose()
+}
+
+/// Loads the key file at `path`, or generates a new one there if it does not exist.
+pub fn load_or_generate(path: &Path) -> Result<KeyBuffer> {
+    if path.exists() {
+        return load(path);
+    }
+    let key = generate(path)?;
+    println!("Generated a new key file at {}", path.display());
+    Ok(key)
+}
+
+/// Writes a new key file with a random key at `path`, which must not exist yet.
+pub fn generate(path: &Path) -> Result<KeyBuffer> {
+    let mut key = KeyBuffer::default();
+    SystemRandom::new()
+        .fill(&mut key)
+        .map_err(|_| RustPassError::Crypto(String::from("Could not generate key")))?;
+    let contents = format!(
+        "{}\n{}\n{}\n",
+        KEY_FILE_HEADER,
+        to_hex(&key),
+        to_hex(&checksum(&key))
+    );
+    let mut file = storage::create_new_private(path).map_err(|source| RustPassError::Io {
+        context: format!("Could not create key file {}", path.display()),
+        source,
+    })?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    Ok(key)
+}
+
+fn parse_generated(text: &str) -> Result<KeyBuffer> {
+    let mut lines = text.lines().skip(1);
+    let key: KeyBuffer = from_hex(lines.next().unwrap_or_default())?
+        .as_slice()
+        .try_into()
+        .map_err(|_| RustPassError::Corrupted(String::from("invalid key length")))?;
+    if from_hex(lines.next().unwrap_or_default())? != checksum(&key) {
+        return Err(RustPassError::Corrupted(String::from("checksum mismatch")).into());
+    }
+    Ok(key)
+}
+
+fn checksum(key: &KeyBuffer) -> Vec<u8> {
+    digest(&SHA256, key).as_ref()[..4].to_vec()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(text: &str) -> Result<Vec<u8>> {
+    let text = text.trim();
+    if !text.len().is_multiple_of(2) {
+        return Err(RustPassError::Corrupted(String::from("invalid hex")).into());
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&text[i..i + 2], 16)
+                .map_err(|_| RustPassError::Corrupted(String::from("invalid hex")).into())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+    use std::{env, process};
+
+    #[test]
+    fn test_key_files() {
+        let generated = env::temp_dir().join(format!("rustpass-keyfile-{}.key", process::id()));
+        let _ = fs::remove_file(&generated);
+        let key = generate(&generated).unwrap();
+        assert_eq!(load(&generated).unwrap(), key);
+        let code = |result: Result<KeyBuffer>| ErrorCode::of(&result.unwrap_err());
+        assert_eq!(code(generate(&generated)), ErrorCode::Io);
+
+        let mut contents = fs::read_to_string(&generated).unwrap();
+        contents.replace_range(25..26, if &contents[25..26] == "0" { "1" } else { "0" });
+        fs::write(&generated, contents).unwrap();
+        assert_eq!(code(load(&generated)), ErrorCode::Corrupted);
+
+        fs::write(&generated, b"any file at all").unwrap();
+        assert_eq!(
+            load(&generated).unwrap().as_slice(),
+            digest(&SHA256, b"any file at all").as_ref()
+        );
+        fs::write(&generated, b"").unwrap();
+        assert_eq!(code(load(&generated)), ErrorCode::InvalidInput);
+        fs::remove_file(&generated).unwrap();
+        assert_eq!(code(load(&generated)), ErrorCode::Io);
+    }
+
+    #[test]
+    fn test_argon_input() {
+        let password = MasterKey::from(String::from("abc"));
+        assert_eq!(password.argon_input(), b"abc");
+        let composite = MasterKey::new(String::from("abc"), Some([1; 32]));
+        let other = MasterKey::new(String::from("abc"), Some([2; 32]));
+        assert_eq!(composite.argon_input().len(), 32);
+        assert_ne!(composite.argon_input(), other.argon_input());
+
+        // "é" typed as a single code point, or as "e" followed by a combining accent
+        let composed = MasterKey::from(String::from("caf\u{e9}"));
+        let decomposed = MasterKey::from(String::from("cafe\u{301}"));
+        assert_eq!(
+            composed.encode(PasswordEncoding::NFC).argon_input(),
+            decomposed.encode(PasswordEncoding::NFC).argon_input()
+        );
+        assert_ne!(
+            composed.encode(PasswordEncoding::TRIMMED).argon_input(),
+            decomposed.encode(PasswordEncoding::TRIMMED).argon_input()
+        );
+        assert_eq!(
+            password.encode(PasswordEncoding::LINE).argon_input(),
+            b"abc\n"
+        );
+    }
+}
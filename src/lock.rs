@@ -0,0 +1,201 @@
+//! Advisory locking of vaults, so that two sessions cannot overwrite each other's changes.
+//!
+//! A session owns a vault while the file `<vault>.lock` exists next to it. The lock file is linked
+//! into place exclusively and records the process ID and host name of its owner, which allows telling a lock
+//! left behind by a crashed session apart from one held by a running session.
+
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+use anyhow::Result;
+use nix::{errno::Errno, sys::signal::kill, unistd::gethostname, unistd::Pid};
+
+use crate::error::RustPassError;
+
+/// Lock held on a vault for as long as the value lives.
+#[derive(Debug)]
+pub struct VaultLock {
+    path: PathBuf,
+}
+
+/// Session that created a lock file.
+#[derive(Debug, PartialEq)]
+pub struct LockOwner {
+    pub pid: u32,
+    pub host: String,
+}
+
+#[derive(Debug)]
+pub enum LockState {
+    Acquired(VaultLock),
+    /// The vault is being edited by a session that is still running, or that runs on another host
+    /// and cannot be checked.
+    Held(LockOwner),
+    /// The lock was left behind by a session on this host which is no longer running.
+    Stale(LockOwner),
+}
+
+/// Path of the lock file of the vault at `path`.
+pub fn lock_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".lock");
+    PathBuf::from(name)
+}
+
+impl VaultLock {
+    /// Tries to lock the vault at `path`, reporting the current owner if it is already locked.
+    pub fn acquire(path: &Path) -> Result<LockState> {
+        let lock_path = lock_path(path);
+        // The owner is written to a temporary file first and linked into place, so that the lock
+        // file never exists without it and another session always finds out who holds it.
+        let mut tmp_name = lock_path.as_os_str().to_owned();
+        tmp_name.push(format!(".{}.tmp", process::id()));
+        let tmp_path = PathBuf::from(tmp_name);
+        let result = (|| {
+            let mut file = File::create(&tmp_path)?;
+            writeln!(file, "{}\n{}", process::id(), local_host())?;
+            file.sync_all()?;
+            fs::hard_link(&tmp_path, &lock_path)
+        })();
+        let _ = fs::remove_file(&tmp_path);
+
+        match result {
+            Ok(()) => Ok(LockState::Acquired(Self { path: lock_path })),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                let owner = LockOwner::read(&lock_path)?;
+                Ok(if owner.is_running() {
+                    LockState::Held(owner)
+                } else {
+                    LockState::Stale(owner)
+                })
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Removes the stale lock left by `owner` and locks the vault for this session instead.
+    pub fn take_over(path: &Path, owner: &LockOwner) -> Result<Self> {
+        if LockOwner::read(&lock_path(path))? != *owner {
+            return Err(RustPassError::Locked(String::from("another session meanwhile")).into());
+        }
+        fs::remove_file(lock_path(path))?;
+        match Self::acquire(path)? {
+            LockState::Acquired(lock) => Ok(lock),
+            LockState::Held(owner) | LockState::Stale(owner) => {
+                Err(RustPassError::Locked(format!("another session meanwhile ({})", owner)).into())
+            }
+        }
+    }
+}
+
+impl Drop for VaultLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl LockOwner {
+    fn read(lock_path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(lock_path)?;
+        let mut lines = contents.lines();
+        let pid = lines.next().and_then(|pid| pid.parse().ok());
+        let host = lines.next();
+        match (pid, host) {
+            (Some(pid), Some(host)) => Ok(Self {
+                pid,
+                host: host.to_string(),
+            }),
+            _ => Err(RustPassError::Corrupted(format!(
+                "Lock file {} is corrupted, remove it if no other session uses the vault",
+                lock_path.display()
+            ))
+            .into()),
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        if self.host != local_host() {
+            return true;
+        }
+        let Ok(pid) = i32::try_from(self.pid) else {
+            return false;
+        };
+        // Signal 0 only checks whether the process exists. EPERM means it exists but belongs to
+        // another user.
+        !matches!(kill(Pid::from_raw(pid), None), Err(Errno::ESRCH))
+    }
+}
+
+impl fmt::Display for LockOwner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "process {} on {}", self.pid, self.host)
+    }
+}
+
+fn local_host() -> String {
+    gethostname()
+        .map(|host| host.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| String::from("localhost"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn vault_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("rustpass-{}-{}.rpdb", name, process::id()))
+    }
+
+    #[test]
+    fn test_lock_is_exclusive() {
+        let path = vault_path("lock");
+        let LockState::Acquired(lock) = VaultLock::acquire(&path).unwrap() else {
+            panic!("vault should not be locked");
+        };
+        match VaultLock::acquire(&path).unwrap() {
+            LockState::Held(owner) => assert_eq!(owner.pid, process::id()),
+            state => panic!("unexpected lock state {:?}", state),
+        }
+
+        drop(lock);
+        assert!(!lock_path(&path).exists());
+        assert!(matches!(
+            VaultLock::acquire(&path).unwrap(),
+            LockState::Acquired(_)
+        ));
+        assert!(!lock_path(&path).exists());
+        let tmp_path = format!("{}.{}.tmp", lock_path(&path).display(), process::id());
+        assert!(!Path::new(&tmp_path).exists());
+    }
+
+    #[test]
+    fn test_stale_lock() {
+        let path = vault_path("stale-lock");
+        // Larger than the maximum PID on Linux, so no such process can exist.
+        fs::write(lock_path(&path), format!("4194305\n{}\n", local_host())).unwrap();
+        let LockState::Stale(owner) = VaultLock::acquire(&path).unwrap() else {
+            panic!("lock should be stale");
+        };
+        let lock = VaultLock::take_over(&path, &owner).unwrap();
+        assert_eq!(
+            LockOwner::read(&lock_path(&path)).unwrap().pid,
+            process::id()
+        );
+        drop(lock);
+
+        fs::write(lock_path(&path), "4194305\nsome-other-host\n").unwrap();
+        assert!(matches!(
+            VaultLock::acquire(&path).unwrap(),
+            LockState::Held(_)
+        ));
+        fs::write(lock_path(&path), "garbage").unwrap();
+        assert!(VaultLock::acquire(&path).is_err());
+        fs::remove_file(lock_path(&path)).unwrap();
+    }
+}
@@ -1,21 +1,81 @@
+mod audit;
+mod blocks;
+mod breach;
 mod commands;
 mod config;
 mod create;
 mod display;
+mod entry;
+mod error;
+mod generate;
+mod keyfile;
+mod lock;
+mod oneshot;
 mod open;
 mod parsing;
+mod passwd;
+mod password;
 mod protos;
+mod slots;
+mod storage;
+mod strength;
 
-use clap::Parser;
+use clap::{
+    error::{ContextKind, ErrorKind},
+    CommandFactory, FromArgMatches,
+};
 use commands::command_factory;
-use display::display_error;
+use display::{display_error, display_error_message, OutputFormat};
+use error::ErrorCode;
 use parsing::MainParser;
+use std::process;
+
+/// Message of a usage error in JSON output: the kind of the error along with the arguments or
+/// values it is about, without the usage and hints clap adds to it.
+fn usage_message(e: &clap::Error) -> String {
+    let mut message = e.kind().to_string();
+    for kind in [
+        ContextKind::InvalidSubcommand,
+        ContextKind::InvalidArg,
+        ContextKind::InvalidValue,
+        ContextKind::PriorArg,
+    ] {
+        if let Some(value) = e.get(kind) {
+            message.push_str(&format!(": {}", value));
+        }
+    }
+    message
+}
 
 fn main() {
-    let parser = MainParser::parse();
-    command_factory(parser.command)
-        .execute()
+    let parser = MainParser::command()
+        .try_get_matches()
+        .and_then(|matches| MainParser::from_arg_matches(&matches))
         .unwrap_or_else(|e| {
-            display_error(e);
-        })
+            // Help and version requests are not errors, and are printed as usual.
+            if matches!(
+                e.kind(),
+                ErrorKind::DisplayHelp
+                    | ErrorKind::DisplayVersion
+                    | ErrorKind::DisplayHelpOnMissingArgumentOrSubcommand
+            ) {
+                e.exit()
+            }
+            // The format is read from whatever could be parsed before the error.
+            let format = MainParser::command()
+                .ignore_errors(true)
+                .try_get_matches()
+                .ok()
+                .and_then(|matches| matches.get_one::<OutputFormat>("format").copied())
+                .unwrap_or_default();
+            if format == OutputFormat::Json {
+                display_error_message(ErrorCode::Usage, &usage_message(&e), format);
+                process::exit(ErrorCode::Usage.exit_code());
+            }
+            e.exit()
+        });
+    if let Err(e) = command_factory(parser.command, parser.format).execute() {
+        let code = display_error(e, parser.format);
+        process::exit(code.exit_code());
+    }
 }
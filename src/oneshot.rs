@@ -0,0 +1,316 @@
+//! One-shot commands, which open a vault, run a single operation on it and exit, for use in
+//! scripts. Values and names are printed on stdout one per line, so they can be piped, or as a
+//! JSON document with `--format json`, while the master password prompt and errors go to stderr.
+//! Failures exit with a status telling their kind, as listed by `ErrorCode`.
+
+use crate::{
+    commands::{split_path, Executable, VaultManager},
+    config::LocalConfig,
+    display::{
+        entry_json, format_rfc3339, format_timestamp, print_entry, print_json, InputReader,
+        OutputFormat,
+    },
+    entry::EntryField,
+    error::RustPassError,
+    keyfile::{self, MasterKey},
+    lock::{LockState, VaultLock},
+    password::PasswordSource,
+    protos::rpdb::Record,
+};
+use anyhow::Result;
+use protobuf::{well_known_types::timestamp::Timestamp, MessageField};
+use serde_json::{json, Value};
+use std::{
+    io::stdin,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Path of a vault, along with the key file it was created with and where to read its master
+/// password from
+pub struct VaultArgs {
+    file_path: String,
+    keyfile: Option<String>,
+    password: PasswordSource,
+}
+
+impl VaultArgs {
+    pub fn new(file_path: String, keyfile: Option<String>, password: PasswordSource) -> Self {
+        Self {
+            file_path,
+            keyfile,
+            password,
+        }
+    }
+
+    fn path(&self) -> Result<PathBuf> {
+        let path = PathBuf::from_str(&self.file_path)?.canonicalize()?;
+        if !path.is_file() {
+            return Err(RustPassError::InvalidInput(String::from(
+                "Path does point to an .rpdb file",
+            ))
+            .into());
+        }
+        Ok(path)
+    }
+
+    fn unlock(&self, path: &Path) -> Result<VaultManager> {
+        let mut vm = VaultManager::new(LocalConfig::load()?);
+        let key_file = keyfile::load_optional(self.keyfile.as_deref())?;
+        vm.initialize_from_file(path, MasterKey::new(self.password.read()?, key_file))?;
+        Ok(vm)
+    }
+
+    /// Opens the vault for reading. It is not locked, since it is never saved.
+    ///
+    /// Reading an entry marks it as accessed in memory only: saving after a read would take the
+    /// lock and rotate the backups just as a change does.
+    pub fn open(&self) -> Result<VaultManager> {
+        self.unlock(&self.path()?)
+    }
+
+    /// Locks and opens the vault for a change. Fails rather than waiting if it is in use.
+    fn open_locked(&self) -> Result<(PathBuf, VaultLock, VaultManager)> {
+        let path = self.path()?;
+        let lock = match VaultLock::acquire(&path)? {
+            LockState::Acquired(lock) => lock,
+            LockState::Held(owner) | LockState::Stale(owner) => {
+                return Err(RustPassError::Locked(owner.to_string()).into())
+            }
+        };
+        let vm = self.unlock(&path)?;
+        Ok((path, lock, vm))
+    }
+}
+
+/// Splits `dir/key` into the path of the directory and the name of the key.
+fn split_key_path(path: &str) -> Result<(String, String)> {
+    let mut names = split_path(path);
+    let key = names.pop().ok_or(RustPassError::InvalidInput(format!(
+        "Missing key name in {}",
+        path
+    )))?;
+    Ok((names.join("/"), key.to_string()))
+}
+
+pub struct GetCommand {
+    vault: VaultArgs,
+    key_path: String,
+    field: Option<String>,
+    format: OutputFormat,
+}
+
+impl GetCommand {
+    pub fn new(
+        vault: VaultArgs,
+        key_path: String,
+        field: Option<String>,
+        format: OutputFormat,
+    ) -> Self {
+        Self {
+            vault,
+            key_path,
+            field,
+            format,
+        }
+    }
+}
+
+impl GetCommand {
+    fn print(&self, text: String, json: Value) {
+        match self.format {
+            OutputFormat::Text => println!("{}", text),
+            OutputFormat::Json => print_json(&json!({
+                "key": self.key_path,
+                "field": self.field.as_deref().unwrap_or("password"),
+                "value": json,
+            })),
+        }
+    }
+}
+
+/// Dates of a record, which `get` prints without opening its entry.
+const DATE_FIELDS: [&str; 4] = ["created", "modified", "accessed", "expires"];
+
+fn record_date<'a>(record: &'a Record, name: &str) -> Option<&'a MessageField<Timestamp>> {
+    match name {
+        "created" => Some(&record.created_at),
+        "modified" => Some(&record.modified_at),
+        "accessed" => Some(&record.accessed_at),
+        "expires" => Some(&record.expires_at),
+        _ => None,
+    }
+}
+
+impl Executable for GetCommand {
+    fn execute(&self) -> Result<()> {
+        let (dir, key) = split_key_path(&self.key_path)?;
+        let name = self.field.as_deref();
+        if let Some(name) = name.filter(|name| DATE_FIELDS.contains(name)) {
+            let mut vm = self.vault.open()?;
+            let dm = vm.open_dir(&dir)?;
+            let date = record_date(dm.record(&key)?, name)
+                .filter(|date| date.is_some())
+                .ok_or(RustPassError::NotFound(String::from("Field")))?;
+            self.print(format_timestamp(date), format_rfc3339(date));
+            return Ok(());
+        }
+
+        let field = name.map(EntryField::parse).transpose()?;
+        let mut vm = self.vault.open()?;
+        let mut dm = vm.open_dir(&dir)?;
+        let value = match &field {
+            Some(field) => dm
+                .get_field(&key, field)?
+                .ok_or(RustPassError::NotFound(String::from("Field")))?,
+            None => dm.get_record(&key)?,
+        };
+        self.print(value.clone(), Value::from(value));
+        Ok(())
+    }
+}
+
+pub struct ShowCommand {
+    vault: VaultArgs,
+    key_path: String,
+    reveal: bool,
+    format: OutputFormat,
+}
+
+impl ShowCommand {
+    pub fn new(vault: VaultArgs, key_path: String, reveal: bool, format: OutputFormat) -> Self {
+        Self {
+            vault,
+            key_path,
+            reveal,
+            format,
+        }
+    }
+}
+
+impl Executable for ShowCommand {
+    fn execute(&self) -> Result<()> {
+        let (dir, key) = split_key_path(&self.key_path)?;
+        let mut vm = self.vault.open()?;
+        let mut dm = vm.open_dir(&dir)?;
+        let entry = dm.get_entry(&key)?;
+        let record = dm.record(&key)?;
+        match self.format {
+            OutputFormat::Text => print_entry(&key, &entry, record, self.reveal),
+            OutputFormat::Json => {
+                print_json(&entry_json(&self.key_path, &entry, record, self.reveal))
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct LsCommand {
+    vault: VaultArgs,
+    path: String,
+    format: OutputFormat,
+}
+
+impl LsCommand {
+    pub fn new(vault: VaultArgs, path: String, format: OutputFormat) -> Self {
+        Self {
+            vault,
+            path,
+            format,
+        }
+    }
+}
+
+impl Executable for LsCommand {
+    fn execute(&self) -> Result<()> {
+        let mut vm = self.vault.open()?;
+        let dm = vm.open_dir(&self.path)?;
+        match self.format {
+            OutputFormat::Text => {
+                for dir in dm.get_directory_names() {
+                    println!("{}/", dir);
+                }
+                for name in dm.get_record_names() {
+                    println!("{}", name);
+                }
+            }
+            OutputFormat::Json => print_json(&json!({
+                "path": self.path,
+                "directories": dm.get_directory_names(),
+                "keys": dm.get_record_names(),
+            })),
+        }
+        Ok(())
+    }
+}
+
+/// Adds a key, whose password is read from stdin after the master password.
+pub struct AddCommand {
+    vault: VaultArgs,
+    key_path: String,
+}
+
+impl AddCommand {
+    pub fn new(vault: VaultArgs, key_path: String) -> Self {
+        Self { vault, key_path }
+    }
+}
+
+impl Executable for AddCommand {
+    fn execute(&self) -> Result<()> {
+        let (dir, key) = split_key_path(&self.key_path)?;
+        let (path, _lock, mut vm) = self.vault.open_locked()?;
+        let mut dm = vm.open_dir(&dir)?;
+        if dm.get_record_names().contains(&key.as_str()) {
+            return Err(RustPassError::AlreadyExists(String::from("Key")).into());
+        }
+        let value = InputReader::read_secret_on_stderr(&mut stdin().lock(), "Value: ")?;
+        dm.add_record(&key, &value)?;
+        vm.save(&path)
+    }
+}
+
+/// Moves a key to the trash, without asking for confirmation.
+pub struct RmCommand {
+    vault: VaultArgs,
+    key_path: String,
+}
+
+impl RmCommand {
+    pub fn new(vault: VaultArgs, key_path: String) -> Self {
+        Self { vault, key_path }
+    }
+}
+
+impl Executable for RmCommand {
+    fn execute(&self) -> Result<()> {
+        let (dir, key) = split_key_path(&self.key_path)?;
+        let (path, _lock, mut vm) = self.vault.open_locked()?;
+        if !vm
+            .open_dir(&dir)?
+            .get_record_names()
+            .contains(&key.as_str())
+        {
+            return Err(RustPassError::NotFound(String::from("Key")).into());
+        }
+        vm.remove_record(&dir, &key)?;
+        vm.save(&path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_key_path() {
+        let split = |path| split_key_path(path).unwrap();
+        assert_eq!(split("key"), (String::new(), String::from("key")));
+        assert_eq!(
+            split("/a/b/key"),
+            (String::from("a/b"), String::from("key"))
+        );
+        assert_eq!(split("a//key/"), (String::from("a"), String::from("key")));
+        assert!(split_key_path("/").is_err());
+    }
+}
@@ -1,17 +1,76 @@
 use crate::{
-    commands::{DirectoryManager, Executable, VaultManager},
-    display::InputReader,
+    audit::{AuditReport, DEFAULT_MAX_AGE},
+    commands::{split_path, trash_item_name, DirectoryManager, Executable, VaultManager},
+    config::LocalConfig,
+    display::{display_error, format_timestamp, print_entry, InputReader, OutputFormat},
+    entry::{parse_date, parse_field_type, EntryField},
+    error::RustPassError,
+    generate::{Generator, PassphrasePolicy, PasswordPolicy},
+    keyfile::{self, MasterKey},
+    lock::{lock_path, LockState, VaultLock},
+    parsing::{GenerateParser, PassphraseParser},
+    passwd::offer_to_remove_backups,
+    password::PasswordSource,
+    protos::rpdb::FieldType,
+};
+use anyhow::Result;
+use clap::Parser;
+use std::{
+    io::{stdin, BufRead},
+    path::{Path, PathBuf},
+    str::FromStr,
 };
-use anyhow::{anyhow, Result};
-use std::{path::PathBuf, str::FromStr};
 
 pub struct OpenCommand {
     file_path: String,
+    read_only: bool,
+    keyfile: Option<String>,
+    password: PasswordSource,
 }
 
 impl OpenCommand {
-    pub fn new(file_path: String) -> Self {
-        Self { file_path }
+    pub fn new(
+        file_path: String,
+        read_only: bool,
+        keyfile: Option<String>,
+        password: PasswordSource,
+    ) -> Self {
+        Self {
+            file_path,
+            read_only,
+            keyfile,
+            password,
+        }
+    }
+
+    /// Locks the vault for the session. Returns `None` if it is in use and the user chose to open
+    /// it read-only instead.
+    fn lock<R: BufRead>(path: &Path, input: &mut R) -> Result<Option<VaultLock>> {
+        match VaultLock::acquire(path)? {
+            LockState::Acquired(lock) => Ok(Some(lock)),
+            LockState::Held(owner) => {
+                let prompt = format!("The vault is in use by {}. Open it read-only?", owner);
+                if !InputReader::read_confirmation(input, &prompt)? {
+                    return Err(RustPassError::Locked(owner.to_string()).into());
+                }
+                Ok(None)
+            }
+            LockState::Stale(owner) => {
+                let prompt = format!(
+                    "The vault was locked by {}, which is no longer running. Take over the lock?",
+                    owner
+                );
+                if !InputReader::read_confirmation(input, &prompt)? {
+                    return Err(RustPassError::Locked(format!(
+                        "{}. Remove {} once no session uses it anymore",
+                        owner,
+                        lock_path(path).display()
+                    ))
+                    .into());
+                }
+                Ok(Some(VaultLock::take_over(path, &owner)?))
+            }
+        }
     }
 }
 
@@ -19,56 +78,145 @@ impl Executable for OpenCommand {
     fn execute(&self) -> Result<()> {
         let path = PathBuf::from_str(&self.file_path)?.canonicalize()?;
         if !path.is_file() {
-            return Err(anyhow!("Path does point to an .rpdb file"));
+            return Err(RustPassError::InvalidInput(String::from(
+                "Path does point to an .rpdb file",
+            ))
+            .into());
         }
-        let mut vm = VaultManager::default();
-        let master = InputReader::read_password()?;
-        vm.initialize_from_file(path, master)?;
-        Ok(())
+        // Held until the session ends, after the vault was saved. Stdin is only locked for the
+        // question, as the master password may be read from it next.
+        let lock = match self.read_only {
+            true => None,
+            false => Self::lock(&path, &mut stdin().lock())?,
+        };
+
+        let mut vm = VaultManager::new(LocalConfig::load()?);
+        let key_file = keyfile::load_optional(self.keyfile.as_deref())?;
+        let master = self.password.read()?;
+        vm.initialize_from_file(&path, MasterKey::new(master, key_file))?;
+        let mut repl = Repl::new(vm, path, Box::new(stdin().lock()));
+        if lock.is_none() {
+            println!("The vault is opened read-only, changes cannot be made");
+            repl.read_only = true;
+        }
+        repl.run()
     }
 }
 
-fn repl(mut vm: VaultManager) {
-    let mut running = false;
-    let mut dm: Option<DirectoryManager> = None;
-    while running {
-        let buf = InputReader::read_command().unwrap(); // FIX: don't use unwrap
-        let contents: Vec<&str> = buf.split_whitespace().collect();
-        let command = ReplCommandType::parse(&contents).unwrap();
-        command.execute(&mut vm, &mut dm, &mut running).unwrap();
+/// Interactive session over an opened vault.
+pub struct Repl {
+    vm: VaultManager,
+    path: PathBuf,
+    curr_dir: Vec<String>,
+    running: bool,
+    modified: bool,
+    read_only: bool,
+    input: Box<dyn BufRead>,
+}
+
+impl Repl {
+    pub fn new(vm: VaultManager, path: PathBuf, input: Box<dyn BufRead>) -> Self {
+        Self {
+            vm,
+            path,
+            curr_dir: vec![],
+            running: false,
+            modified: false,
+            read_only: false,
+            input,
+        }
+    }
+
+    /// Runs commands until `exit` or the end of the input, then writes the vault back if it was
+    /// modified during the session.
+    pub fn run(&mut self) -> Result<()> {
+        self.running = true;
+        while self.running {
+            let location = format!("/{}", self.curr_dir.join("/"));
+            // An unreadable input ends the session like the end of the input does, so that the
+            // changes made so far are still saved.
+            let buf = match InputReader::read_command(&mut self.input, &location) {
+                Ok(Some(buf)) => buf,
+                Ok(None) => break,
+                Err(e) => {
+                    display_error(e, OutputFormat::Text);
+                    break;
+                }
+            };
+            let contents: Vec<&str> = buf.split_whitespace().collect();
+            if contents.is_empty() {
+                continue;
+            }
+            if let Err(e) = ReplCommandType::parse(&contents).and_then(|cmd| cmd.execute(self)) {
+                display_error(e, OutputFormat::Text);
+            }
+        }
+
+        if self.modified && !self.read_only {
+            self.vm.save(&self.path)?;
+            self.modified = false;
+        }
+        Ok(())
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(
+                RustPassError::InvalidInput(String::from("The vault is opened read-only")).into(),
+            );
+        }
+        Ok(())
+    }
+
+    fn current_dir(&mut self) -> Result<DirectoryManager<'_>> {
+        self.vm.open_dir(&self.curr_dir.join("/"))
+    }
+
+    /// Resolves a path typed by the user against the current directory. Absolute paths start
+    /// with `/`, and `..` refers to the parent directory.
+    fn resolve(&self, path: &str) -> Vec<String> {
+        let mut resolved = if path.starts_with('/') {
+            vec![]
+        } else {
+            self.curr_dir.clone()
+        };
+        for component in split_path(path) {
+            match component {
+                "." => {}
+                ".." => {
+                    resolved.pop();
+                }
+                name => resolved.push(name.to_string()),
+            }
+        }
+        resolved
     }
 }
 
 trait ReplCommand: Sized {
     fn parse(args: &[&str]) -> Result<Self>;
-    fn execute(
-        &self,
-        vm: &mut VaultManager,
-        dm: &mut Option<DirectoryManager>,
-        running: &mut bool,
-    ) -> Result<()>;
+    fn execute(&self, repl: &mut Repl) -> Result<()>;
 }
 
 macro_rules! help {
     () => {
-        anyhow!("Incorrect usage of command. Try running help.")
+        anyhow::Error::from(RustPassError::InvalidInput(String::from(
+            "Incorrect usage of command. Try running help.",
+        )))
     };
 }
 
 struct LSCommand;
 
 impl ReplCommand for LSCommand {
-    fn execute(
-        &self,
-        vm: &mut VaultManager,
-        dm: &mut Option<DirectoryManager>,
-        running: &mut bool,
-    ) -> Result<()> {
-        //let directories = match repl.curr_dir {
-        //    None => repl.vm.get_directories(),
-        //    Some(dir) => {}
-        //}
-        //
+    fn execute(&self, repl: &mut Repl) -> Result<()> {
+        let dm = repl.current_dir()?;
+        for dir in dm.get_directory_names() {
+            println!("{}/", dir);
+        }
+        for name in dm.get_record_names() {
+            println!("{}", name);
+        }
         Ok(())
     }
 
@@ -81,25 +229,53 @@ impl ReplCommand for LSCommand {
     }
 }
 
+struct CdCommand {
+    path: String,
+}
+
+impl ReplCommand for CdCommand {
+    fn execute(&self, repl: &mut Repl) -> Result<()> {
+        let path = repl.resolve(&self.path);
+        repl.vm.open_dir(&path.join("/"))?;
+        repl.curr_dir = path;
+        Ok(())
+    }
+
+    fn parse(args: &[&str]) -> Result<Self> {
+        if args.len() > 1 {
+            return Err(help!());
+        }
+        Ok(Self {
+            path: args.first().unwrap_or(&"/").to_string(),
+        })
+    }
+}
+
 struct MKDirCommand {
-    dir_name: String,
+    path: String,
 }
 
 impl MKDirCommand {
     fn validate_dir_name(dir_name: &str) -> Result<&str> {
         if !dir_name.chars().all(|c| c.is_alphanumeric()) {
-            return Err(anyhow!(
-                "Directory name must only consist of alphanumeric characters"
-            ));
+            return Err(RustPassError::InvalidInput(String::from(
+                "Directory name must only consist of alphanumeric characters",
+            ))
+            .into());
         }
 
         if dir_name
             .chars()
             .next()
-            .ok_or_else(|| anyhow!("Directory name cannot be empty"))?
+            .ok_or(RustPassError::InvalidInput(String::from(
+                "Directory name cannot be empty",
+            )))?
             .is_numeric()
         {
-            return Err(anyhow!("Directory name cannot start with a number"));
+            return Err(RustPassError::InvalidInput(String::from(
+                "Directory name cannot start with a number",
+            ))
+            .into());
         }
 
         Ok(dir_name)
@@ -107,65 +283,368 @@ impl MKDirCommand {
 }
 
 impl ReplCommand for MKDirCommand {
-    fn execute(
-        &self,
-        vm: &mut VaultManager,
-        dm: &mut Option<DirectoryManager>,
-        running: &mut bool,
-    ) -> Result<()> {
-        unimplemented!()
+    fn execute(&self, repl: &mut Repl) -> Result<()> {
+        repl.check_writable()?;
+        let path = repl.resolve(&self.path);
+        repl.vm.add_directory(&path.join("/"))?;
+        repl.modified = true;
+        Ok(())
     }
     fn parse(args: &[&str]) -> Result<Self> {
-        const NARGS: usize = 2;
+        const NARGS: usize = 1;
         if args.len() != NARGS {
             return Err(help!());
         }
-        let dir_name = Self::validate_dir_name(args[0])?;
+        let path = args[0].trim_end_matches('/');
+        Self::validate_dir_name(path.rsplit('/').next().unwrap_or_default())?;
 
         Ok(Self {
-            dir_name: dir_name.to_string(),
+            path: path.to_string(),
         })
     }
 }
 
 struct GetCommand {
     key_name: String,
+    field: Option<EntryField>,
 }
 
 impl ReplCommand for GetCommand {
-    fn execute(
-        &self,
-        vm: &mut VaultManager,
-        dm: &mut Option<DirectoryManager>,
-        running: &mut bool,
-    ) -> Result<()> {
-        unimplemented!()
+    fn execute(&self, repl: &mut Repl) -> Result<()> {
+        let mut dm = repl.current_dir()?;
+        let value = match &self.field {
+            Some(field) => dm
+                .get_field(&self.key_name, field)?
+                .ok_or(RustPassError::NotFound(String::from("Field")))?,
+            None => dm.get_record(&self.key_name)?,
+        };
+        println!("{}", value);
+        Ok(())
     }
     fn parse(args: &[&str]) -> Result<Self> {
-        const NARGS: usize = 1;
+        if args.is_empty() || args.len() > 2 {
+            return Err(help!());
+        }
 
-        if args.len() != NARGS {
+        Ok(Self {
+            key_name: args[0].to_string(),
+            field: args
+                .get(1)
+                .map(|name| EntryField::parse(name))
+                .transpose()?,
+        })
+    }
+}
+
+struct ShowCommand {
+    key_name: String,
+    reveal: bool,
+}
+
+impl ReplCommand for ShowCommand {
+    fn execute(&self, repl: &mut Repl) -> Result<()> {
+        let mut dm = repl.current_dir()?;
+        let entry = dm.get_entry(&self.key_name)?;
+        print_entry(
+            &self.key_name,
+            &entry,
+            dm.record(&self.key_name)?,
+            self.reveal,
+        );
+        Ok(())
+    }
+    fn parse(args: &[&str]) -> Result<Self> {
+        let reveal = match args {
+            [_] => false,
+            [_, "-r" | "--reveal"] => true,
+            _ => return Err(help!()),
+        };
+
+        Ok(Self {
+            key_name: args[0].to_string(),
+            reveal,
+        })
+    }
+}
+
+struct EditCommand {
+    key_name: String,
+    field: String,
+    field_type: Option<FieldType>,
+    protected: Option<bool>,
+}
+
+impl EditCommand {
+    fn edit_expiry(&self, repl: &mut Repl) -> Result<()> {
+        repl.current_dir()?.record(&self.key_name)?;
+        let value = InputReader::read_line(
+            &mut repl.input,
+            "Expires on (YYYY-MM-DD, empty for never): ",
+        )?;
+        let expires_at = match value.trim() {
+            "" => None,
+            date => Some(parse_date(date)?),
+        };
+        repl.current_dir()?.set_expiry(&self.key_name, expires_at)
+    }
+}
+
+impl ReplCommand for EditCommand {
+    fn execute(&self, repl: &mut Repl) -> Result<()> {
+        repl.check_writable()?;
+        if self.field == "expires" {
+            self.edit_expiry(repl)?;
+            repl.modified = true;
+            return Ok(());
+        }
+
+        let field = EntryField::parse(&self.field)?;
+        let has_options = self.field_type.is_some() || self.protected.is_some();
+        if has_options && !matches!(field, EntryField::Custom(_)) {
+            return Err(RustPassError::InvalidInput(String::from(
+                "Only custom fields have a type and a protection",
+            ))
+            .into());
+        }
+        let entry = repl.current_dir()?.get_entry(&self.key_name)?;
+        let prompt = format!("{}: ", self.field);
+        let value = match self.protected.unwrap_or(entry.is_protected(&field)) {
+            true => InputReader::read_secret(&mut repl.input, &prompt)?,
+            false => InputReader::read_line(&mut repl.input, &prompt)?,
+        };
+
+        let mut dm = repl.current_dir()?;
+        match &field {
+            EntryField::Custom(name) => {
+                let existing = entry.custom_field(name);
+                let field_type = self
+                    .field_type
+                    .or(existing.map(|f| f.type_.enum_value_or_default()))
+                    .unwrap_or(FieldType::TEXT);
+                let protected = self
+                    .protected
+                    .unwrap_or(existing.is_some_and(|f| f.protected));
+                dm.update_entry(&self.key_name, |entry| {
+                    entry.set_custom(name, &value, field_type, protected)
+                })?;
+            }
+            field => dm.set_field(&self.key_name, field, &value)?,
+        }
+        repl.modified = true;
+        Ok(())
+    }
+    fn parse(args: &[&str]) -> Result<Self> {
+        if args.len() < 2 {
             return Err(help!());
         }
 
+        let mut field_type = None;
+        let mut protected = None;
+        for option in &args[2..] {
+            match *option {
+                "protected" => protected = Some(true),
+                "unprotected" => protected = Some(false),
+                name => field_type = Some(parse_field_type(name)?),
+            }
+        }
         Ok(Self {
             key_name: args[0].to_string(),
+            field: args[1].to_string(),
+            field_type,
+            protected,
         })
     }
 }
 
 struct AddCommand {
     key_name: String,
+    generate: Option<Generator>,
 }
 
 impl ReplCommand for AddCommand {
-    fn execute(
-        &self,
-        vm: &mut VaultManager,
-        dm: &mut Option<DirectoryManager>,
-        running: &mut bool,
-    ) -> Result<()> {
-        unimplemented!()
+    fn execute(&self, repl: &mut Repl) -> Result<()> {
+        repl.check_writable()?;
+        if repl
+            .current_dir()?
+            .get_record_names()
+            .contains(&self.key_name.as_str())
+        {
+            return Err(RustPassError::AlreadyExists(String::from("Key")).into());
+        }
+        let value = match &self.generate {
+            Some(generator) => generator.generate()?,
+            None => InputReader::read_secret(&mut repl.input, "Value: ")?,
+        };
+        repl.current_dir()?.add_record(&self.key_name, &value)?;
+        repl.modified = true;
+        if let Some(generator) = &self.generate {
+            println!(
+                "Generated a value with {:.1} bits of entropy",
+                generator.entropy()
+            );
+        }
+        Ok(())
+    }
+    fn parse(args: &[&str]) -> Result<Self> {
+        let generate = match args {
+            [_] => None,
+            [_, "--generate", options @ ..] => {
+                let options = GenerateParser::try_parse_from(options).map_err(|_| help!())?;
+                Some(Generator::Password(PasswordPolicy::new(&options.args)?))
+            }
+            [_, "--passphrase", options @ ..] => {
+                let options = PassphraseParser::try_parse_from(options).map_err(|_| help!())?;
+                Some(Generator::Passphrase(PassphrasePolicy::new(&options.args)?))
+            }
+            _ => return Err(help!()),
+        };
+
+        Ok(Self {
+            key_name: args[0].to_string(),
+            generate,
+        })
+    }
+}
+
+struct HistoryCommand {
+    key_name: String,
+}
+
+impl ReplCommand for HistoryCommand {
+    fn execute(&self, repl: &mut Repl) -> Result<()> {
+        let mut dm = repl.current_dir()?;
+        let current = dm.get_entry(&self.key_name)?;
+        let history = dm.get_history(&self.key_name)?;
+        if history.is_empty() {
+            println!("No previous versions of {}", self.key_name);
+        }
+        let versions = &dm.record(&self.key_name)?.history;
+        for (index, (version, entry)) in versions.iter().zip(&history).enumerate() {
+            let modified_at = version
+                .modified_at
+                .as_ref()
+                .map(format_timestamp)
+                .unwrap_or_default();
+            let changed = current.changed_fields(entry);
+            println!(
+                "{:<3} {:<24} {}",
+                index + 1,
+                modified_at,
+                match changed.is_empty() {
+                    true => String::from("same as the current version"),
+                    false => format!("differs in {}", changed.join(", ")),
+                }
+            );
+        }
+        Ok(())
+    }
+    fn parse(args: &[&str]) -> Result<Self> {
+        const NARGS: usize = 1;
+
+        if args.len() != NARGS {
+            return Err(help!());
+        }
+
+        Ok(Self {
+            key_name: args[0].to_string(),
+        })
+    }
+}
+
+struct RevertCommand {
+    key_name: String,
+    version: usize,
+}
+
+impl ReplCommand for RevertCommand {
+    fn execute(&self, repl: &mut Repl) -> Result<()> {
+        repl.check_writable()?;
+        repl.current_dir()?
+            .restore_version(&self.key_name, self.version - 1)?;
+        repl.modified = true;
+        Ok(())
+    }
+    fn parse(args: &[&str]) -> Result<Self> {
+        const NARGS: usize = 2;
+
+        if args.len() != NARGS {
+            return Err(help!());
+        }
+        let version = args[1].parse().ok().filter(|&version| version > 0).ok_or(
+            RustPassError::InvalidInput(String::from("Version must be a number listed by history")),
+        )?;
+
+        Ok(Self {
+            key_name: args[0].to_string(),
+            version,
+        })
+    }
+}
+
+struct RmCommand {
+    key_name: String,
+}
+
+impl ReplCommand for RmCommand {
+    fn execute(&self, repl: &mut Repl) -> Result<()> {
+        repl.check_writable()?;
+        if !repl
+            .current_dir()?
+            .get_record_names()
+            .contains(&self.key_name.as_str())
+        {
+            return Err(RustPassError::NotFound(String::from("Key")).into());
+        }
+        let prompt = format!("Remove key {}?", self.key_name);
+        if !InputReader::read_confirmation(&mut repl.input, &prompt)? {
+            return Ok(());
+        }
+        repl.vm
+            .remove_record(&repl.curr_dir.join("/"), &self.key_name)?;
+        repl.modified = true;
+        Ok(())
+    }
+    fn parse(args: &[&str]) -> Result<Self> {
+        const NARGS: usize = 1;
+
+        if args.len() != NARGS {
+            return Err(help!());
+        }
+
+        Ok(Self {
+            key_name: args[0].to_string(),
+        })
+    }
+}
+
+struct RmDirCommand {
+    path: String,
+}
+
+impl ReplCommand for RmDirCommand {
+    fn execute(&self, repl: &mut Repl) -> Result<()> {
+        repl.check_writable()?;
+        let path = repl.resolve(&self.path);
+        if repl.curr_dir.starts_with(&path) {
+            return Err(RustPassError::InvalidInput(String::from(
+                "Cannot remove the current directory or one of its parents",
+            ))
+            .into());
+        }
+        let path = path.join("/");
+        let dm = repl.vm.open_dir(&path)?;
+        let prompt = format!(
+            "Remove directory /{} with {} key(s) and {} subdirectory(ies)?",
+            path,
+            dm.get_record_names().len(),
+            dm.get_directory_names().len()
+        );
+        if !InputReader::read_confirmation(&mut repl.input, &prompt)? {
+            return Ok(());
+        }
+        repl.vm.remove_directory(&path)?;
+        repl.modified = true;
+        Ok(())
     }
     fn parse(args: &[&str]) -> Result<Self> {
         const NARGS: usize = 1;
@@ -174,8 +653,285 @@ impl ReplCommand for AddCommand {
             return Err(help!());
         }
 
+        Ok(Self {
+            path: args[0].to_string(),
+        })
+    }
+}
+
+enum TrashCommand {
+    List,
+    Restore(usize),
+    Empty,
+}
+
+impl ReplCommand for TrashCommand {
+    fn execute(&self, repl: &mut Repl) -> Result<()> {
+        match self {
+            Self::List => {
+                if repl.vm.trash().is_empty() {
+                    println!("The trash is empty");
+                }
+                for (index, item) in repl.vm.trash().iter().enumerate() {
+                    let deleted_at = item
+                        .deleted_at
+                        .as_ref()
+                        .map(format_timestamp)
+                        .unwrap_or_default();
+                    let parent = match item.path.is_empty() {
+                        true => String::new(),
+                        false => format!("/{}", item.path),
+                    };
+                    println!(
+                        "{:<3} {:<24} {}/{}",
+                        index + 1,
+                        deleted_at,
+                        parent,
+                        trash_item_name(item)
+                    );
+                }
+            }
+            Self::Restore(index) => {
+                repl.check_writable()?;
+                repl.vm.restore_from_trash(index - 1)?;
+                repl.modified = true;
+            }
+            Self::Empty => {
+                repl.check_writable()?;
+                let prompt = format!(
+                    "Permanently delete the {} item(s) in the trash?",
+                    repl.vm.trash().len()
+                );
+                if !InputReader::read_confirmation(&mut repl.input, &prompt)? {
+                    return Ok(());
+                }
+                repl.vm.empty_trash();
+                repl.modified = true;
+            }
+        }
+        Ok(())
+    }
+    fn parse(args: &[&str]) -> Result<Self> {
+        Ok(match args {
+            [] | ["ls"] => Self::List,
+            ["restore", index] => {
+                Self::Restore(index.parse().ok().filter(|&index| index > 0).ok_or(
+                    RustPassError::InvalidInput(String::from(
+                        "Item must be a number listed by trash",
+                    )),
+                )?)
+            }
+            ["empty"] => Self::Empty,
+            _ => return Err(help!()),
+        })
+    }
+}
+
+struct MvCommand {
+    key_name: String,
+    path: String,
+}
+
+impl ReplCommand for MvCommand {
+    fn execute(&self, repl: &mut Repl) -> Result<()> {
+        repl.check_writable()?;
+        let to = repl.resolve(&self.path).join("/");
+        repl.vm
+            .move_record(&repl.curr_dir.join("/"), &self.key_name, &to)?;
+        repl.modified = true;
+        Ok(())
+    }
+    fn parse(args: &[&str]) -> Result<Self> {
+        const NARGS: usize = 2;
+
+        if args.len() != NARGS {
+            return Err(help!());
+        }
+
         Ok(Self {
             key_name: args[0].to_string(),
+            path: args[1].to_string(),
+        })
+    }
+}
+
+struct RenameCommand {
+    old_name: String,
+    new_name: String,
+}
+
+impl ReplCommand for RenameCommand {
+    fn execute(&self, repl: &mut Repl) -> Result<()> {
+        repl.check_writable()?;
+        let is_dir = repl
+            .current_dir()?
+            .get_directory_names()
+            .contains(&self.old_name.as_str());
+        if is_dir {
+            MKDirCommand::validate_dir_name(&self.new_name)?;
+            let path = repl.resolve(&self.old_name).join("/");
+            repl.vm.rename_directory(&path, &self.new_name)?;
+        } else {
+            repl.current_dir()?
+                .rename_record(&self.old_name, &self.new_name)?;
+        }
+        repl.modified = true;
+        Ok(())
+    }
+    fn parse(args: &[&str]) -> Result<Self> {
+        const NARGS: usize = 2;
+
+        if args.len() != NARGS {
+            return Err(help!());
+        }
+
+        Ok(Self {
+            old_name: args[0].trim_end_matches('/').to_string(),
+            new_name: args[1].trim_end_matches('/').to_string(),
+        })
+    }
+}
+
+struct PasswdCommand;
+
+impl ReplCommand for PasswdCommand {
+    fn execute(&self, repl: &mut Repl) -> Result<()> {
+        repl.check_writable()?;
+        let current =
+            InputReader::read_master_password(&mut repl.input, "Current master password: ")?;
+        repl.vm.verify_master_password(current)?;
+        let master = InputReader::read_new_master_password(&mut repl.input)?;
+        let key_file = repl.vm.key_file();
+        repl.vm
+            .change_master_password(MasterKey::new(master, key_file))?;
+        // Saved right away rather than on exit, so that the new password is in effect even if
+        // the session does not end normally.
+        repl.vm.save(&repl.path)?;
+        repl.modified = false;
+        println!("Master password changed");
+        offer_to_remove_backups(&mut repl.input, &repl.path)?;
+        if repl.vm.slots().len() > 1 {
+            println!("Only the key slot this session was unlocked with was changed");
+        }
+        Ok(())
+    }
+    fn parse(args: &[&str]) -> Result<Self> {
+        const NARGS: usize = 0;
+
+        if args.len() != NARGS {
+            return Err(help!());
+        }
+
+        Ok(Self)
+    }
+}
+
+enum SlotCommand {
+    List,
+    Add {
+        name: String,
+        keyfile: Option<String>,
+    },
+    Revoke(usize),
+}
+
+impl ReplCommand for SlotCommand {
+    fn execute(&self, repl: &mut Repl) -> Result<()> {
+        match self {
+            Self::List => {
+                for (index, slot) in repl.vm.slots().iter().enumerate() {
+                    let components = match slot.uses_key_file() {
+                        true => "password and key file",
+                        false => "password",
+                    };
+                    let current = match index == repl.vm.slot_index() {
+                        true => " (unlocked this session)",
+                        false => "",
+                    };
+                    println!(
+                        "{:<3} {:<16} {}{}",
+                        index + 1,
+                        slot.name,
+                        components,
+                        current
+                    );
+                }
+            }
+            Self::Add { name, keyfile } => {
+                repl.check_writable()?;
+                let master = InputReader::read_new_master_password(&mut repl.input)?;
+                let key_file = keyfile
+                    .as_deref()
+                    .map(|path| keyfile::load_or_generate(Path::new(path)))
+                    .transpose()?;
+                repl.vm.add_slot(name, MasterKey::new(master, key_file))?;
+                repl.modified = true;
+            }
+            Self::Revoke(index) => {
+                repl.check_writable()?;
+                let name = &repl
+                    .vm
+                    .slots()
+                    .get(index - 1)
+                    .ok_or(RustPassError::InvalidInput(String::from(
+                        "Key slot must be a number listed by slot",
+                    )))?
+                    .name;
+                let prompt = format!("Revoke key slot {}?", name);
+                if !InputReader::read_confirmation(&mut repl.input, &prompt)? {
+                    return Ok(());
+                }
+                repl.vm.revoke_slot(index - 1)?;
+                repl.modified = true;
+            }
+        }
+        Ok(())
+    }
+    fn parse(args: &[&str]) -> Result<Self> {
+        Ok(match args {
+            [] | ["ls"] => Self::List,
+            ["add", name] | ["add", name, _] => Self::Add {
+                name: name.to_string(),
+                keyfile: args.get(2).map(|path| path.to_string()),
+            },
+            ["rm", index] => Self::Revoke(index.parse().ok().filter(|&index| index > 0).ok_or(
+                RustPassError::InvalidInput(String::from(
+                    "Key slot must be a number listed by slot",
+                )),
+            )?),
+            _ => return Err(help!()),
+        })
+    }
+}
+
+struct AuditCommand {
+    max_age: u32,
+    breached: Option<String>,
+}
+
+impl ReplCommand for AuditCommand {
+    fn execute(&self, repl: &mut Repl) -> Result<()> {
+        let breached = self.breached.as_deref().map(Path::new);
+        AuditReport::new(&mut repl.vm, self.max_age, breached)?.print(self.max_age);
+        Ok(())
+    }
+    fn parse(args: &[&str]) -> Result<Self> {
+        let (days, breached) = match args {
+            [] => (None, None),
+            [days] => (Some(days), None),
+            ["--breached", file] => (None, Some(file)),
+            [days, "--breached", file] => (Some(days), Some(file)),
+            _ => return Err(help!()),
+        };
+        let max_age = match days {
+            Some(days) => days.parse().map_err(|_| {
+                RustPassError::InvalidInput(String::from("Age must be a number of days"))
+            })?,
+            None => DEFAULT_MAX_AGE,
+        };
+        Ok(Self {
+            max_age,
+            breached: breached.map(|file| file.to_string()),
         })
     }
 }
@@ -183,13 +939,9 @@ impl ReplCommand for AddCommand {
 struct ExitCommand;
 
 impl ReplCommand for ExitCommand {
-    fn execute(
-        &self,
-        vm: &mut VaultManager,
-        dm: &mut Option<DirectoryManager>,
-        running: &mut bool,
-    ) -> Result<()> {
-        unimplemented!()
+    fn execute(&self, repl: &mut Repl) -> Result<()> {
+        repl.running = false;
+        Ok(())
     }
     fn parse(args: &[&str]) -> Result<Self> {
         const NARGS: usize = 0;
@@ -202,12 +954,85 @@ impl ReplCommand for ExitCommand {
     }
 }
 
+struct HelpCommand;
+
+impl ReplCommand for HelpCommand {
+    fn execute(&self, _repl: &mut Repl) -> Result<()> {
+        println!("ls              list the directories and keys of the current directory");
+        println!("cd [PATH]       enter a directory (`..` for the parent), or go back to the root");
+        println!("mkdir PATH      create a directory");
+        println!("get KEY [FIELD] print the password of a key, or another of its fields");
+        println!("show KEY [-r]   print all the fields of a key, revealing protected ones with -r");
+        println!("add KEY         add a key to the current directory");
+        println!("add KEY --generate [-l LENGTH] [--lower] [--upper] [--digits] [--symbols]");
+        println!("    [--custom SET] [--no-ambiguous] [--require-each]");
+        println!("                add a key with a random password, drawn from the selected");
+        println!("                character classes, or from all of them when none is selected");
+        println!("add KEY --passphrase [-w WORDS] [-s SEPARATOR] [--capitalize] [--digit]");
+        println!("    [--wordlist PATH]");
+        println!("                add a key with a random passphrase");
+        println!("edit KEY FIELD [TYPE] [protected|unprotected]");
+        println!("                change a field of a key: username, password, url, notes,");
+        println!("                expires or a custom field of type text, url, email, number");
+        println!("                or date");
+        println!("history KEY     list the previous versions of a key");
+        println!("revert KEY N    restore the N'th previous version of a key");
+        println!("rm KEY          remove a key from the current directory");
+        println!("rmdir PATH      remove a directory and everything inside it");
+        println!("                removed keys and directories are moved to the trash");
+        println!("trash [ls]      list the items in the trash");
+        println!("trash restore N put the N'th item of the trash back where it was");
+        println!("trash empty     permanently delete the items in the trash");
+        println!("mv KEY PATH     move a key from the current directory to PATH");
+        println!("rename OLD NEW  rename a key or a subdirectory of the current directory");
+        println!("passwd          change the master password and save the vault. With a single");
+        println!("                key slot the data key is replaced too; with several, only the");
+        println!("                slot of this session is changed and the data key is kept");
+        println!("slot [ls]       list the key slots, each unlocking the vault with its own");
+        println!("                master password and key file");
+        println!("slot add NAME [KEYFILE]");
+        println!("                add a key slot, generating KEYFILE if it does not exist. Once");
+        println!("                there are several, passwd no longer replaces the data key");
+        println!("slot rm N       revoke the N'th key slot");
+        println!("audit [DAYS] [--breached FILE]");
+        println!("                report weak, short and reused passwords, and those not changed");
+        println!("                in DAYS days, 365 by default. With --breached, passwords are");
+        println!("                also looked up in a local Have I Been Pwned hash file");
+        println!("exit            save changes and leave");
+        Ok(())
+    }
+    fn parse(args: &[&str]) -> Result<Self> {
+        const NARGS: usize = 0;
+
+        if args.len() != NARGS {
+            return Err(help!());
+        }
+
+        Ok(Self)
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
 enum ReplCommandType {
     LS(LSCommand),
+    CD(CdCommand),
     MKDIR(MKDirCommand),
     GET(GetCommand),
+    SHOW(ShowCommand),
     ADD(AddCommand),
+    EDIT(EditCommand),
+    HISTORY(HistoryCommand),
+    REVERT(RevertCommand),
+    RM(RmCommand),
+    RMDIR(RmDirCommand),
+    TRASH(TrashCommand),
+    MV(MvCommand),
+    RENAME(RenameCommand),
+    PASSWD(PasswdCommand),
+    SLOT(SlotCommand),
+    AUDIT(AuditCommand),
     EXIT(ExitCommand),
+    HELP(HelpCommand),
 }
 
 macro_rules! delegate {
@@ -215,30 +1040,357 @@ macro_rules! delegate {
         fn $method(&self, $($arg: $arg_type,)*) -> $ret_type{
             match self{
                 ReplCommandType::LS(cmd) => cmd.$method($($arg), *),
+                ReplCommandType::CD(cmd) => cmd.$method($($arg), *),
                 ReplCommandType::MKDIR(cmd) => cmd.$method($($arg), *),
                 ReplCommandType::GET(cmd) => cmd.$method($($arg), *),
+                ReplCommandType::SHOW(cmd) => cmd.$method($($arg), *),
                 ReplCommandType::ADD(cmd) => cmd.$method($($arg), *),
+                ReplCommandType::EDIT(cmd) => cmd.$method($($arg), *),
+                ReplCommandType::HISTORY(cmd) => cmd.$method($($arg), *),
+                ReplCommandType::REVERT(cmd) => cmd.$method($($arg), *),
+                ReplCommandType::RM(cmd) => cmd.$method($($arg), *),
+                ReplCommandType::RMDIR(cmd) => cmd.$method($($arg), *),
+                ReplCommandType::TRASH(cmd) => cmd.$method($($arg), *),
+                ReplCommandType::MV(cmd) => cmd.$method($($arg), *),
+                ReplCommandType::RENAME(cmd) => cmd.$method($($arg), *),
+                ReplCommandType::PASSWD(cmd) => cmd.$method($($arg), *),
+                ReplCommandType::SLOT(cmd) => cmd.$method($($arg), *),
+                ReplCommandType::AUDIT(cmd) => cmd.$method($($arg), *),
                 ReplCommandType::EXIT(cmd) => cmd.$method($($arg), *),
+                ReplCommandType::HELP(cmd) => cmd.$method($($arg), *),
             }
         }
     };
 }
 
 impl ReplCommand for ReplCommandType {
-    delegate!(self, execute, vm: &mut VaultManager, dm: &mut Option<DirectoryManager>, running: &mut bool => Result<()>);
+    delegate!(self, execute, repl: &mut Repl => Result<()>);
 
     fn parse(args: &[&str]) -> Result<Self> {
         if args.is_empty() {
-            return Err(anyhow!("No command provided"));
+            return Err(RustPassError::InvalidInput(String::from("No command provided")).into());
         }
 
         Ok(match args[0] {
             "ls" => Self::LS(LSCommand::parse(&args[1..])?),
+            "cd" => Self::CD(CdCommand::parse(&args[1..])?),
             "mkdir" => Self::MKDIR(MKDirCommand::parse(&args[1..])?),
             "get" => Self::GET(GetCommand::parse(&args[1..])?),
+            "show" => Self::SHOW(ShowCommand::parse(&args[1..])?),
             "add" => Self::ADD(AddCommand::parse(&args[1..])?),
+            "edit" => Self::EDIT(EditCommand::parse(&args[1..])?),
+            "history" => Self::HISTORY(HistoryCommand::parse(&args[1..])?),
+            "revert" => Self::REVERT(RevertCommand::parse(&args[1..])?),
+            "rm" => Self::RM(RmCommand::parse(&args[1..])?),
+            "rmdir" => Self::RMDIR(RmDirCommand::parse(&args[1..])?),
+            "trash" => Self::TRASH(TrashCommand::parse(&args[1..])?),
+            "mv" => Self::MV(MvCommand::parse(&args[1..])?),
+            "rename" => Self::RENAME(RenameCommand::parse(&args[1..])?),
+            "passwd" => Self::PASSWD(PasswdCommand::parse(&args[1..])?),
+            "slot" => Self::SLOT(SlotCommand::parse(&args[1..])?),
+            "audit" => Self::AUDIT(AuditCommand::parse(&args[1..])?),
             "exit" => Self::EXIT(ExitCommand::parse(&args[1..])?),
-            _ => return Err(anyhow!("Invalid command")),
+            "help" => Self::HELP(HelpCommand::parse(&args[1..])?),
+            _ => return Err(RustPassError::InvalidInput(String::from("Invalid command")).into()),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{backup_path, remove_with_backups};
+    use std::{env, io::Cursor, process};
+
+    const MASTER_PASSWORD: &str = "abcdefgh";
+
+    fn create_vault(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("rustpass-{}-{}.rpdb", name, process::id()));
+        let mut vm = VaultManager::default();
+        vm.regenerate(MASTER_PASSWORD.to_string()).unwrap();
+        vm.save(&path).unwrap();
+        path
+    }
+
+    fn run_script(path: &PathBuf, script: &str) {
+        let mut vm = VaultManager::default();
+        vm.initialize_from_file(path, MASTER_PASSWORD.to_string())
+            .unwrap();
+        let input = Box::new(Cursor::new(script.to_string().into_bytes()));
+        Repl::new(vm, path.clone(), input).run().unwrap();
+    }
+
+    fn reopen(path: &PathBuf) -> VaultManager {
+        let mut vm = VaultManager::default();
+        vm.initialize_from_file(path, MASTER_PASSWORD.to_string())
+            .unwrap();
+        vm
+    }
+
+    #[test]
+    fn test_mkdir_add_get() {
+        let path = create_vault("mkdir-add-get");
+        run_script(
+            &path,
+            "mkdir email\nmkdir bank\ncd email\nadd gmail\nhunter2\nls\nget gmail\ncd ..\nexit\n",
+        );
+
+        let mut vm = reopen(&path);
+        assert_eq!(
+            vm.open_dir("").unwrap().get_directory_names(),
+            vec!["email", "bank"]
+        );
+        let mut dm = vm.open_dir("email").unwrap();
+        assert_eq!(dm.get_record_names(), vec!["gmail"]);
+        assert_eq!(dm.get_record("gmail").unwrap(), "hunter2");
+        remove_with_backups(&path);
+    }
+
+    #[test]
+    fn test_add_generate() {
+        let path = create_vault("add-generate");
+        run_script(
+            &path,
+            "add a --generate\nadd b --generate -l 8 --digits --require-each\n\
+             add c --generate -l 0\nadd d --generate --bogus\nadd e --passphrase -w 3 -s _\nexit\n",
+        );
+
+        let mut vm = reopen(&path);
+        let mut dm = vm.open_dir("").unwrap();
+        assert_eq!(dm.get_record_names(), vec!["a", "b", "e"]);
+        assert_eq!(dm.get_record("a").unwrap().chars().count(), 20);
+        let b = dm.get_record("b").unwrap();
+        assert_eq!(b.len(), 8);
+        assert!(b.chars().all(|c| c.is_ascii_digit()));
+        assert_eq!(dm.get_record("e").unwrap().split('_').count(), 3);
+        remove_with_backups(&path);
+    }
+
+    #[test]
+    fn test_errors_do_not_stop_session() {
+        let path = create_vault("errors");
+        run_script(
+            &path,
+            "frobnicate\nget missing\ncd missing\nmkdir 1abc\nmkdir ok\nmkdir ok\ncd ok\nmkdir nested\n",
+        );
+
+        let mut vm = reopen(&path);
+        assert_eq!(vm.open_dir("").unwrap().get_directory_names(), vec!["ok"]);
+        remove_with_backups(&path);
+    }
+
+    #[test]
+    fn test_rm_rmdir_mv_rename() {
+        let path = create_vault("rm-mv");
+        run_script(
+            &path,
+            "mkdir a\nmkdir b\nmkdir c\ncd a\nadd k1\nv1\nadd k2\nv2\nadd k3\nv3\n\
+             rm k1\nn\nrm k2\ny\nmv k3 ../b\ncd ..\nrmdir c\nno\nrmdir a\nyes\n\
+             rename b d\ncd d\nrename k3 k4\nexit\n",
+        );
+
+        let mut vm = reopen(&path);
+        assert_eq!(
+            vm.open_dir("").unwrap().get_directory_names(),
+            vec!["d", "c"]
+        );
+        let mut dm = vm.open_dir("d").unwrap();
+        assert_eq!(dm.get_record_names(), vec!["k4"]);
+        assert_eq!(dm.get_record("k4").unwrap(), "v3");
+        remove_with_backups(&path);
+    }
+
+    #[test]
+    fn test_nested_navigation() {
+        let path = create_vault("nested");
+        run_script(
+            &path,
+            "mkdir infra\ncd infra\nmkdir prod\nmkdir prod/db\ncd prod/db\nadd password\npw\n\
+             cd ../..\nmkdir staging\ncd /infra/staging\nrmdir ..\nrmdir /infra/prod\nn\n\
+             cd ../prod/db\nmv password /infra/staging\ncd /\nrename infra ops\nexit\n",
+        );
+
+        let mut vm = reopen(&path);
+        assert_eq!(
+            vm.open_dir("ops").unwrap().get_directory_names(),
+            vec!["prod", "staging"]
+        );
+        assert!(vm
+            .open_dir("ops/prod/db")
+            .unwrap()
+            .get_record_names()
+            .is_empty());
+        assert_eq!(
+            vm.open_dir("ops/staging")
+                .unwrap()
+                .get_record("password")
+                .unwrap(),
+            "pw"
+        );
+        remove_with_backups(&path);
+    }
+
+    #[test]
+    fn test_changes_are_saved_on_invalid_input() {
+        let path = create_vault("invalid-input");
+        let vm = reopen(&path);
+        let mut script = b"add mail\nhunter2\n".to_vec();
+        script.extend_from_slice(b"ls \xff\xfe\nmkdir ignored\n");
+        Repl::new(vm, path.clone(), Box::new(Cursor::new(script)))
+            .run()
+            .unwrap();
+
+        let mut vm = reopen(&path);
+        let mut dm = vm.open_dir("").unwrap();
+        assert_eq!(dm.get_record("mail").unwrap(), "hunter2");
+        assert!(dm.get_directory_names().is_empty());
+        remove_with_backups(&path);
+    }
+
+    #[test]
+    fn test_unmodified_vault_is_not_rewritten() {
+        let path = create_vault("unmodified");
+        let before = std::fs::read(&path).unwrap();
+        run_script(&path, "ls\nhelp\nexit\nmkdir ignored\n");
+        assert_eq!(std::fs::read(&path).unwrap(), before);
+        remove_with_backups(&path);
+    }
+
+    #[test]
+    fn test_access_time_is_saved_with_changes() {
+        let path = create_vault("accessed");
+        run_script(&path, "add mail\nhunter2\nexit\n");
+        let before = std::fs::read(&path).unwrap();
+        run_script(&path, "get mail\nshow mail\nexit\n");
+        assert_eq!(std::fs::read(&path).unwrap(), before);
+        run_script(&path, "get mail\nmkdir email\nexit\n");
+        assert!(reopen(&path)
+            .open_dir("")
+            .unwrap()
+            .record("mail")
+            .unwrap()
+            .accessed_at
+            .is_some());
+        remove_with_backups(&path);
+    }
+
+    #[test]
+    fn test_show_edit() {
+        let path = create_vault("show-edit");
+        run_script(
+            &path,
+            "add mail\nhunter2\nedit mail username\nbob\nedit mail url\nhttps://mail.example\n\
+             edit mail pin number protected\n12a4\nedit mail pin number protected\n1234\n\
+             edit mail expires\n2030-01-01\nedit mail username text\nshow mail\nget mail pin\n",
+        );
+
+        let mut vm = reopen(&path);
+        let mut dm = vm.open_dir("").unwrap();
+        let entry = dm.get_entry("mail").unwrap();
+        assert_eq!(entry.username, "bob");
+        assert_eq!(entry.password, "hunter2");
+        assert_eq!(entry.urls, vec!["https://mail.example"]);
+        let pin = entry.custom_field("pin").unwrap();
+        assert_eq!(pin.value, "1234");
+        assert!(pin.protected);
+        let expires_at = dm.record("mail").unwrap().expires_at.clone().unwrap();
+        assert_eq!(format_timestamp(&expires_at), "2030-01-01");
+        remove_with_backups(&path);
+    }
+
+    #[test]
+    fn test_history_revert() {
+        let path = create_vault("history-revert");
+        run_script(
+            &path,
+            "add mail\nfirst\nedit mail password\nsecond\nedit mail notes\nhello\n\
+             history mail\nrevert mail 3\nrevert mail 2\n",
+        );
+
+        let mut vm = reopen(&path);
+        let mut dm = vm.open_dir("").unwrap();
+        let entry = dm.get_entry("mail").unwrap();
+        assert_eq!(entry.password, "first");
+        assert!(entry.notes.is_empty());
+        let history = dm.get_history("mail").unwrap();
+        let passwords: Vec<&str> = history.iter().map(|e| e.password.as_str()).collect();
+        assert_eq!(passwords, vec!["second", "second", "first"]);
+        remove_with_backups(&path);
+    }
+
+    #[test]
+    fn test_trash() {
+        let path = create_vault("repl-trash");
+        run_script(
+            &path,
+            "mkdir email\ncd email\nadd gmail\nsecret\nrm gmail\ny\ncd /\nrmdir email\ny\n\
+             trash\ntrash restore 2\ntrash restore 1\nmkdir bank\nrmdir bank\ny\n",
+        );
+
+        let mut vm = reopen(&path);
+        assert_eq!(vm.trash().len(), 1);
+        assert_eq!(
+            vm.open_dir("email").unwrap().get_record("gmail").unwrap(),
+            "secret"
+        );
+        run_script(&path, "trash empty\nn\ntrash empty\ny\n");
+        assert!(reopen(&path).trash().is_empty());
+        remove_with_backups(&path);
+    }
+
+    #[test]
+    fn test_passwd() {
+        let path = env::temp_dir().join(format!("rustpass-repl-passwd-{}.rpdb", process::id()));
+        let mut vm = VaultManager::default();
+        vm.regenerate(String::from("old")).unwrap();
+        vm.open_dir("").unwrap().add_record("key", "value").unwrap();
+        vm.save(&path).unwrap();
+
+        let script = "passwd\nwrong\npasswd\nold\nnew\nnwe\npasswd\nold\nnew\nnew\ny\n";
+        let input = Box::new(Cursor::new(script.as_bytes().to_vec()));
+        Repl::new(vm, path.clone(), input).run().unwrap();
+
+        let mut vm = VaultManager::default();
+        assert!(vm.initialize_from_file(&path, String::from("old")).is_err());
+        vm.initialize_from_file(&path, String::from("new")).unwrap();
+        assert_eq!(vm.open_dir("").unwrap().get_record("key").unwrap(), "value");
+        assert!(!backup_path(&path, 1).exists());
+        remove_with_backups(&path);
+    }
+
+    #[test]
+    fn test_read_only_session() {
+        let path = create_vault("read-only");
+        run_script(&path, "mkdir email\n");
+        let before = std::fs::read(&path).unwrap();
+
+        let input = Box::new(Cursor::new(b"mkdir bank\nrmdir email\ny\nls\n".to_vec()));
+        let mut repl = Repl::new(reopen(&path), path.clone(), input);
+        repl.read_only = true;
+        repl.run().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), before);
+        assert_eq!(
+            reopen(&path).open_dir("").unwrap().get_directory_names(),
+            vec!["email"]
+        );
+        remove_with_backups(&path);
+    }
+
+    #[test]
+    fn test_lock_prompts() {
+        let path = create_vault("lock-prompts");
+        let LockState::Acquired(lock) = VaultLock::acquire(&path).unwrap() else {
+            panic!("vault should not be locked");
+        };
+        assert!(OpenCommand::lock(&path, &mut Cursor::new(b"n\n")).is_err());
+        assert!(OpenCommand::lock(&path, &mut Cursor::new(b"y\n"))
+            .unwrap()
+            .is_none());
+        drop(lock);
+
+        let lock = OpenCommand::lock(&path, &mut Cursor::new(b"")).unwrap();
+        assert!(lock.is_some());
+        drop(lock);
+        remove_with_backups(&path);
+    }
+}
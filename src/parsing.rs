@@ -1,25 +1,329 @@
-use clap::{Parser, Subcommand};
+use crate::{audit::DEFAULT_MAX_AGE, display::OutputFormat};
+use clap::{Args, Parser, Subcommand};
 
 #[derive(Parser)]
 #[command(name = "RustPass")]
 #[command(about = "A rust-based password manager.", long_about = None)]
 pub struct MainParser {
+    /// Output format of get, ls, show, audit and errors
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+
     #[command(subcommand)]
     pub command: Commands,
 }
 
 #[derive(Subcommand)]
 pub enum Commands {
+    /// Create a new vault
     Create {
         #[arg(short, long)]
         name: String,
 
         #[arg(short, long, default_value = ".")]
         dir: String,
+
+        /// Require a key file along with the master password. A new key file is generated if
+        /// none exists at this path, otherwise the existing file is used
+        #[arg(long, value_name = "PATH")]
+        keyfile: Option<String>,
+
+        #[command(flatten)]
+        password: PasswordArgs,
     },
+    /// Open a vault in an interactive session
     Open {
         #[arg(value_name = "PATH_TO_FILE")]
         file_path: String,
+
+        /// Open the vault without locking it. No changes can be made
+        #[arg(long)]
+        read_only: bool,
+
+        /// Key file the vault was created with
+        #[arg(long, value_name = "PATH")]
+        keyfile: Option<String>,
+
+        #[command(flatten)]
+        password: PasswordArgs,
+    },
+    /// Print the password of a key, or another of its fields
+    Get {
+        #[arg(value_name = "PATH_TO_FILE")]
+        file_path: String,
+
+        /// Path of the key in the vault, such as `email/work`
+        #[arg(value_name = "KEY")]
+        key_path: String,
+
+        /// Field to print instead of the password: username, url, notes, the name of a custom
+        /// field, or one of the dates created, modified, accessed and expires
+        field: Option<String>,
+
+        /// Key file the vault was created with
+        #[arg(long, value_name = "PATH")]
+        keyfile: Option<String>,
+
+        #[command(flatten)]
+        password: PasswordArgs,
+    },
+    /// Print every field of a key, along with when it was created, changed and last read
+    Show {
+        #[arg(value_name = "PATH_TO_FILE")]
+        file_path: String,
+
+        /// Path of the key in the vault, such as `email/work`
+        #[arg(value_name = "KEY")]
+        key_path: String,
+
+        /// Print the password and protected fields rather than masking them
+        #[arg(short, long)]
+        reveal: bool,
+
+        /// Key file the vault was created with
+        #[arg(long, value_name = "PATH")]
+        keyfile: Option<String>,
+
+        #[command(flatten)]
+        password: PasswordArgs,
+    },
+    /// Add a key, reading its password from stdin
+    Add {
+        #[arg(value_name = "PATH_TO_FILE")]
+        file_path: String,
+
+        /// Path of the new key in the vault, in an existing directory
+        #[arg(value_name = "KEY")]
+        key_path: String,
+
+        /// Key file the vault was created with
+        #[arg(long, value_name = "PATH")]
+        keyfile: Option<String>,
+
+        #[command(flatten)]
+        password: PasswordArgs,
+    },
+    /// List the subdirectories, with a trailing `/`, and keys of a directory
+    Ls {
+        #[arg(value_name = "PATH_TO_FILE")]
+        file_path: String,
+
+        /// Directory in the vault, the root by default
+        #[arg(value_name = "DIR", default_value = "")]
+        path: String,
+
+        /// Key file the vault was created with
+        #[arg(long, value_name = "PATH")]
+        keyfile: Option<String>,
+
+        #[command(flatten)]
+        password: PasswordArgs,
+    },
+    /// Move a key to the trash
+    Rm {
+        #[arg(value_name = "PATH_TO_FILE")]
+        file_path: String,
+
+        /// Path of the key in the vault
+        #[arg(value_name = "KEY")]
+        key_path: String,
+
+        /// Key file the vault was created with
+        #[arg(long, value_name = "PATH")]
+        keyfile: Option<String>,
+
+        #[command(flatten)]
+        password: PasswordArgs,
+    },
+    /// Change the master password of a vault.
+    /// With a single key slot the data key is replaced as well; with several, only the slot it
+    /// is unlocked with is changed and the data key is kept, so that the others still open it.
+    Passwd {
+        #[arg(value_name = "PATH_TO_FILE")]
+        file_path: String,
+
+        /// Key file the vault currently uses
+        #[arg(long, value_name = "PATH")]
+        keyfile: Option<String>,
+
+        /// Use another key file from now on, generating it if it does not exist
+        #[arg(long, value_name = "PATH", conflicts_with = "remove_keyfile")]
+        new_keyfile: Option<String>,
+
+        /// Stop requiring a key file
+        #[arg(long)]
+        remove_keyfile: bool,
+
+        #[command(flatten)]
+        password: PasswordArgs,
+    },
+    /// Show the configuration, or update it with the given values.
+    /// The key derivation settings only apply to vaults created afterwards.
+    Config {
+        /// Size of the blocks vaults are written in, in KiB, up to 65536
+        #[arg(long)]
+        chunk_size: Option<u32>,
+
+        /// Number of Argon2 passes
+        #[arg(long)]
+        iterations: Option<u32>,
+
+        /// Argon2 memory cost, in MiB
+        #[arg(long)]
+        memory: Option<u32>,
+
+        /// Number of Argon2 lanes
+        #[arg(long)]
+        parallelism: Option<u32>,
+
+        /// Number of backups kept next to a vault, 0 to disable them
+        #[arg(long)]
+        backups: Option<u32>,
+
+        /// Number of previous versions kept in each key, 0 to disable them
+        #[arg(long)]
+        history: Option<u32>,
+
+        /// Number of days deleted keys and directories stay in the trash, 0 to keep them until
+        /// it is emptied
+        #[arg(long)]
+        trash_retention: Option<u32>,
+
+        /// Whether new master passwords are normalized to Unicode NFC, so that they give the
+        /// same key whichever way their accented characters were typed
+        #[arg(long, value_name = "BOOL")]
+        unicode_normalization: Option<bool>,
+    },
+    /// Report weak, short, reused and old passwords in a vault
+    Audit {
+        #[arg(value_name = "PATH_TO_FILE")]
+        file_path: String,
+
+        /// Key file the vault was created with
+        #[arg(long, value_name = "PATH")]
+        keyfile: Option<String>,
+
+        /// Report passwords not changed in this many days, 0 to never report them
+        #[arg(long, value_name = "DAYS", default_value_t = DEFAULT_MAX_AGE)]
+        max_age: u32,
+
+        /// Look passwords up in a local Have I Been Pwned dump: a file of SHA-1 hashes sorted by
+        /// hash, or a directory of range files. Nothing is sent over the network
+        #[arg(long, value_name = "FILE")]
+        breached: Option<String>,
+
+        #[command(flatten)]
+        password: PasswordArgs,
+    },
+    /// Generate a random password. With no character class selected, lower and upper case
+    /// letters, digits and symbols are used
+    Generate {
+        #[command(flatten)]
+        args: GenerateArgs,
+    },
+    /// Generate a random passphrase of words from a wordlist
+    Passphrase {
+        #[command(flatten)]
+        args: PassphraseArgs,
     },
-    Config,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct GenerateArgs {
+    /// Number of characters
+    #[arg(short, long, default_value_t = 20)]
+    pub length: usize,
+
+    /// Use lower case letters
+    #[arg(long)]
+    pub lower: bool,
+
+    /// Use upper case letters
+    #[arg(long)]
+    pub upper: bool,
+
+    /// Use digits
+    #[arg(long)]
+    pub digits: bool,
+
+    /// Use symbols
+    #[arg(long)]
+    pub symbols: bool,
+
+    /// Use the characters of SET as another class
+    #[arg(long, value_name = "SET")]
+    pub custom: Option<String>,
+
+    /// Leave out characters that are easily mistaken for one another, such as 1, l and I
+    #[arg(long)]
+    pub no_ambiguous: bool,
+
+    /// Include at least one character of each class
+    #[arg(long)]
+    pub require_each: bool,
+}
+
+/// Options of `add KEY --generate` in the REPL
+#[derive(Parser)]
+#[command(no_binary_name = true)]
+pub struct GenerateParser {
+    #[command(flatten)]
+    pub args: GenerateArgs,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct PassphraseArgs {
+    /// Number of words
+    #[arg(short, long, default_value_t = 6)]
+    pub words: usize,
+
+    /// Text put between words
+    #[arg(short, long, default_value = "-")]
+    pub separator: String,
+
+    /// Start every word with a capital letter
+    #[arg(long)]
+    pub capitalize: bool,
+
+    /// Append a random digit to one of the words
+    #[arg(long)]
+    pub digit: bool,
+
+    /// Draw words from this file, with a word per line, instead of the bundled wordlist. EFF
+    /// wordlists, with dice rolls before each word, are supported as well
+    #[arg(long, value_name = "PATH")]
+    pub wordlist: Option<String>,
+}
+
+/// Options of `add KEY --passphrase` in the REPL
+#[derive(Parser)]
+#[command(no_binary_name = true)]
+pub struct PassphraseParser {
+    #[command(flatten)]
+    pub args: PassphraseArgs,
+}
+
+/// Where the master password is read from, instead of asking for it on the terminal
+#[derive(Args, Clone, Debug)]
+#[group(multiple = false)]
+pub struct PasswordArgs {
+    /// Read the master password from the first line of stdin
+    #[arg(long)]
+    pub password_stdin: bool,
+
+    /// Read the master password from the first line of an open file descriptor
+    #[arg(long, value_name = "N")]
+    pub password_fd: Option<i32>,
+
+    /// Read the master password from the first line of a file
+    #[arg(long, value_name = "PATH")]
+    pub password_file: Option<String>,
+
+    /// Run a shell command and use the first line it prints as the master password
+    #[arg(long, value_name = "COMMAND")]
+    pub password_command: Option<String>,
+
+    /// Read the master password from an environment variable
+    #[arg(long, value_name = "VAR")]
+    pub password_env: Option<String>,
 }
@@ -0,0 +1,96 @@
+use crate::{
+    commands::{Executable, VaultManager},
+    config::LocalConfig,
+    display::InputReader,
+    error::RustPassError,
+    keyfile::{self, MasterKey},
+    lock::{LockState, VaultLock},
+    password::PasswordSource,
+    storage,
+};
+use anyhow::Result;
+use std::{
+    io::{stdin, BufRead},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+pub struct PasswdCommand {
+    file_path: String,
+    keyfile: Option<String>,
+    new_keyfile: Option<String>,
+    remove_keyfile: bool,
+    password: PasswordSource,
+}
+
+impl PasswdCommand {
+    pub fn new(
+        file_path: String,
+        keyfile: Option<String>,
+        new_keyfile: Option<String>,
+        remove_keyfile: bool,
+        password: PasswordSource,
+    ) -> Self {
+        Self {
+            file_path,
+            keyfile,
+            new_keyfile,
+            remove_keyfile,
+            password,
+        }
+    }
+}
+
+impl Executable for PasswdCommand {
+    fn execute(&self) -> Result<()> {
+        let path = PathBuf::from_str(&self.file_path)?.canonicalize()?;
+        if !path.is_file() {
+            return Err(RustPassError::InvalidInput(String::from(
+                "Path does point to an .rpdb file",
+            ))
+            .into());
+        }
+        let _lock = match VaultLock::acquire(&path)? {
+            LockState::Acquired(lock) => lock,
+            LockState::Held(owner) | LockState::Stale(owner) => {
+                return Err(RustPassError::Locked(owner.to_string()).into())
+            }
+        };
+
+        let mut vm = VaultManager::new(LocalConfig::load()?);
+        let key_file = keyfile::load_optional(self.keyfile.as_deref())?;
+        vm.initialize_from_file(&path, MasterKey::new(self.password.read()?, key_file))?;
+
+        let new_key_file = match &self.new_keyfile {
+            Some(path) => Some(keyfile::load_or_generate(Path::new(path))?),
+            None if self.remove_keyfile => None,
+            None => vm.key_file(),
+        };
+        let master = InputReader::read_new_master_password(&mut stdin().lock())?;
+        vm.change_master_password(MasterKey::new(master, new_key_file))?;
+        vm.save(&path)?;
+        println!("Master password changed");
+        offer_to_remove_backups(&mut stdin().lock(), &path)?;
+        if vm.slots().len() > 1 {
+            println!("Only the key slot the vault was unlocked with was changed");
+        }
+        Ok(())
+    }
+}
+
+/// Backups keep opening with the master password they were written with, so after a change they
+/// are deleted if the user agrees.
+pub fn offer_to_remove_backups<R: BufRead>(input: &mut R, path: &Path) -> Result<()> {
+    let count = storage::count_backups(path);
+    if count == 0 {
+        return Ok(());
+    }
+    let prompt = format!(
+        "{} backup(s) still open with the old master password. Delete them?",
+        count
+    );
+    if InputReader::read_confirmation(input, &prompt)? {
+        storage::remove_backups(path)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,172 @@
+//! Sources the master password can be read from, so that vaults can be opened without a terminal.
+
+use crate::{display::InputReader, error::RustPassError, parsing::PasswordArgs};
+use anyhow::Result;
+use std::{
+    env::{self, VarError},
+    fs::File,
+    io::{stdin, BufRead, BufReader},
+    process::{Command, Stdio},
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PasswordSource {
+    /// Asked for on the terminal, without echo
+    Prompt,
+    /// First line of stdin. Anything after it is left for the command to read
+    Stdin,
+    /// First line read from an inherited file descriptor
+    Fd(i32),
+    /// First line of a file
+    File(String),
+    /// First line printed by a shell command, such as a secret manager client
+    Command(String),
+    /// Value of an environment variable
+    Env(String),
+}
+
+impl From<&PasswordArgs> for PasswordSource {
+    fn from(value: &PasswordArgs) -> Self {
+        if value.password_stdin {
+            Self::Stdin
+        } else if let Some(fd) = value.password_fd {
+            Self::Fd(fd)
+        } else if let Some(path) = &value.password_file {
+            Self::File(path.clone())
+        } else if let Some(command) = &value.password_command {
+            Self::Command(command.clone())
+        } else if let Some(name) = &value.password_env {
+            Self::Env(name.clone())
+        } else {
+            Self::Prompt
+        }
+    }
+}
+
+impl PasswordSource {
+    /// Reads the master password, without its line ending.
+    pub fn read(&self) -> Result<String> {
+        Ok(match self {
+            Self::Prompt => return InputReader::read_password(),
+            Self::Stdin => first_line(&mut stdin().lock())?,
+            Self::Fd(fd) => {
+                // Opened through /dev/fd, which duplicates the descriptor rather than taking
+                // ownership of it.
+                let file =
+                    File::open(format!("/dev/fd/{}", fd)).map_err(|source| RustPassError::Io {
+                        context: format!("Could not read file descriptor {}", fd),
+                        source,
+                    })?;
+                first_line(&mut BufReader::new(file))?
+            }
+            Self::File(path) => {
+                let file = File::open(path).map_err(|source| RustPassError::Io {
+                    context: format!("Could not read password file {}", path),
+                    source,
+                })?;
+                first_line(&mut BufReader::new(file))?
+            }
+            Self::Command(command) => {
+                let output = Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .stdin(Stdio::null())
+                    .stderr(Stdio::inherit())
+                    .output()
+                    .map_err(|source| RustPassError::Io {
+                        context: String::from("Could not run password command"),
+                        source,
+                    })?;
+                if !output.status.success() {
+                    return Err(RustPassError::InvalidInput(format!(
+                        "Password command failed with {}",
+                        output.status
+                    ))
+                    .into());
+                }
+                first_line(&mut output.stdout.as_slice())?
+            }
+            Self::Env(name) => {
+                let value = env::var(name).map_err(|e| match e {
+                    VarError::NotPresent => {
+                        RustPassError::NotFound(format!("Environment variable {}", name))
+                    }
+                    VarError::NotUnicode(_) => RustPassError::InvalidInput(format!(
+                        "Environment variable {} is not valid unicode",
+                        name
+                    )),
+                })?;
+                first_line(&mut value.as_bytes())?
+            }
+        })
+    }
+
+    /// Reads the master password of a new vault. At the prompt it is asked for twice, to catch
+    /// typos before the vault is sealed with it.
+    pub fn read_new(&self) -> Result<String> {
+        if *self == Self::Prompt {
+            return InputReader::read_new_master_password(&mut stdin().lock());
+        }
+        let password = self.read()?;
+        if password.is_empty() {
+            return Err(RustPassError::InvalidInput(String::from(
+                "Master password cannot be empty",
+            ))
+            .into());
+        }
+        InputReader::warn_if_weak(&password);
+        Ok(password)
+    }
+}
+
+/// First line of `reader`, without its line ending.
+fn first_line<R: BufRead>(reader: &mut R) -> Result<String> {
+    let mut buf = String::new();
+    if reader.read_line(&mut buf)? == 0 {
+        return Err(
+            RustPassError::InvalidInput(String::from("No master password was given")).into(),
+        );
+    }
+    Ok(buf.trim_end_matches(['\n', '\r']).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+    use std::{fs, process};
+
+    #[test]
+    fn test_sources() {
+        let path = env::temp_dir().join(format!("rustpass-password-{}", process::id()));
+        fs::write(&path, "hunter2\r\nignored\n").unwrap();
+        let file = PasswordSource::File(path.to_string_lossy().into_owned());
+        assert_eq!(file.read().unwrap(), "hunter2");
+        fs::write(&path, "").unwrap();
+        assert_eq!(
+            ErrorCode::of(&file.read().unwrap_err()),
+            ErrorCode::InvalidInput
+        );
+        fs::remove_file(&path).unwrap();
+        assert_eq!(ErrorCode::of(&file.read().unwrap_err()), ErrorCode::Io);
+
+        let command = PasswordSource::Command(String::from("printf hunter2"));
+        assert_eq!(command.read().unwrap(), "hunter2");
+        assert!(PasswordSource::Command(String::from("echo"))
+            .read_new()
+            .is_err());
+        assert!(PasswordSource::Command(String::from("exit 3"))
+            .read()
+            .is_err());
+
+        let name = format!("RUSTPASS_TEST_PASSWORD_{}", process::id());
+        let source = PasswordSource::Env(name.clone());
+        assert_eq!(
+            ErrorCode::of(&source.read().unwrap_err()),
+            ErrorCode::NotFound
+        );
+        env::set_var(&name, "hunter2\n");
+        assert_eq!(source.read().unwrap(), "hunter2");
+        env::remove_var(&name);
+    }
+}
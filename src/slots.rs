@@ -0,0 +1,156 @@
+//! Key slots. The keys of a vault are derived from a random data key rather than from a master
+//! key, and every slot holds a copy of the data key wrapped under the Argon2 hash of its own
+//! master key. Slots can be added and revoked without sealing the vault again.
+
+use anyhow::Result;
+use protobuf::{EnumOrUnknown, MessageField};
+use ring::aead::{Aad, Nonce};
+
+use crate::{
+    commands::{stored_nonce, KeyBuffer, KeyGen},
+    error::RustPassError,
+    keyfile::MasterKey,
+    protos::rpdb::{KdfParams, KeyComponent, KeySlot, PasswordEncoding},
+};
+
+/// Components of `master_key`, as recorded in headers and slots.
+pub fn key_components(master_key: &MasterKey) -> Vec<EnumOrUnknown<KeyComponent>> {
+    let mut components = vec![EnumOrUnknown::new(KeyComponent::PASSWORD)];
+    if master_key.key_file().is_some() {
+        components.push(EnumOrUnknown::new(KeyComponent::KEY_FILE));
+    }
+    components
+}
+
+pub fn uses_key_file(components: &[EnumOrUnknown<KeyComponent>]) -> bool {
+    components.contains(&EnumOrUnknown::new(KeyComponent::KEY_FILE))
+}
+
+impl KeySlot {
+    /// Wraps `data_key` under `master_key`, encoded with `encoding` and hashed with a fresh salt
+    /// and `params`.
+    pub fn wrap(
+        name: &str,
+        master_key: &MasterKey,
+        data_key: &KeyBuffer,
+        params: KdfParams,
+        encoding: PasswordEncoding,
+    ) -> Result<Self> {
+        let mut slot = Self::new();
+        slot.name = name.into();
+        slot.argon_salt = KeyGen::random_key()?.to_vec();
+        slot.key_components = key_components(master_key);
+        slot.password_encoding = EnumOrUnknown::new(encoding);
+
+        let wrapping_key = KeyGen::hash_master_key(
+            &master_key.encode(encoding),
+            &slot.argon_salt,
+            Some(&params),
+        )?;
+        let key = KeyGen::derive_key(&wrapping_key, &slot.argon_salt)?;
+        let nonce = KeyGen::get_unique_nonce()?;
+        let mut wrapped_key = data_key.to_vec();
+        key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(name),
+            &mut wrapped_key,
+        )
+        .map_err(|_| RustPassError::Crypto(String::from("Could not wrap key")))?;
+
+        slot.kdf_params = MessageField::some(params);
+        slot.nonce = nonce.to_vec();
+        slot.wrapped_key = wrapped_key;
+        Ok(slot)
+    }
+
+    /// Unwraps the data key with `master_key`, failing if it is not the master key of this slot.
+    pub fn unwrap(&self, master_key: &MasterKey) -> Result<KeyBuffer> {
+        if self.uses_key_file() != master_key.key_file().is_some() {
+            return Err(RustPassError::KeyFileMismatch {
+                required: self.uses_key_file(),
+            }
+            .into());
+        }
+        let encoding = self.password_encoding.enum_value().map_err(|_| {
+            RustPassError::Corrupted(format!(
+                "Key slot {} has an unknown password encoding",
+                self.name
+            ))
+        })?;
+        let wrapping_key = KeyGen::hash_master_key(
+            &master_key.encode(encoding),
+            &self.argon_salt,
+            self.kdf_params.as_ref(),
+        )?;
+        let key = KeyGen::derive_key(&wrapping_key, &self.argon_salt)?;
+        let nonce = stored_nonce(&self.nonce)?;
+        let mut buf = self.wrapped_key.clone();
+        let data_key = key
+            .open_in_place(nonce, Aad::from(self.name.as_str()), &mut buf)
+            .map_err(|_| RustPassError::WrongPassword)?;
+        let result = KeyBuffer::try_from(&*data_key)
+            .map_err(|_| RustPassError::Corrupted(String::from("Key slot is corrupted")).into());
+        buf.fill(0);
+        result
+    }
+
+    pub fn uses_key_file(&self) -> bool {
+        uses_key_file(&self.key_components)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_unwrap() {
+        let mut params = KdfParams::new();
+        params.iterations = 1;
+        params.memory = 64;
+        params.parallelism = 1;
+        let data_key = [7; 32];
+        let master_key = MasterKey::from(String::from("abc"));
+        let slot = KeySlot::wrap(
+            "alice",
+            &master_key,
+            &data_key,
+            params.clone(),
+            PasswordEncoding::NFC,
+        )
+        .unwrap();
+        assert_eq!(slot.unwrap(&master_key).unwrap(), data_key);
+        assert!(slot.unwrap(&String::from("abd").into()).is_err());
+        assert!(slot
+            .unwrap(&MasterKey::new(String::from("abc"), Some([1; 32])))
+            .is_err());
+
+        let mut renamed = slot.clone();
+        renamed.name = "mallory".into();
+        assert!(renamed.unwrap(&master_key).is_err());
+
+        let with_key_file = MasterKey::new(String::from("abc"), Some([1; 32]));
+        let slot = KeySlot::wrap(
+            "bob",
+            &with_key_file,
+            &data_key,
+            params.clone(),
+            PasswordEncoding::NFC,
+        )
+        .unwrap();
+        assert!(slot.uses_key_file());
+        assert_eq!(slot.unwrap(&with_key_file).unwrap(), data_key);
+
+        // Slots written before 0x0009 hashed the password along with its line ending.
+        let slot = KeySlot::wrap(
+            "carol",
+            &master_key,
+            &data_key,
+            params,
+            PasswordEncoding::LINE,
+        )
+        .unwrap();
+        assert_eq!(slot.unwrap(&master_key).unwrap(), data_key);
+        assert!(slot.unwrap(&String::from("abc\n").into()).is_err());
+    }
+}
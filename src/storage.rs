@@ -0,0 +1,204 @@
+//! Crash-safe writing of vault files.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    process,
+};
+
+use anyhow::Result;
+
+use crate::error::RustPassError;
+
+/// Path of the `index`'th backup of `path`. `vault.rpdb.bak.1` is the most recent one.
+pub fn backup_path(path: &Path, index: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".bak.{}", index));
+    PathBuf::from(name)
+}
+
+/// Keeps the current contents of `path` as its most recent backup, shifting the older ones and
+/// dropping those beyond `count`.
+fn rotate_backups(path: &Path, count: u32) -> Result<()> {
+    if count == 0 || !path.exists() {
+        return Ok(());
+    }
+    for index in (1..count).rev() {
+        let from = backup_path(path, index);
+        if from.exists() {
+            fs::rename(&from, backup_path(path, index + 1))?;
+        }
+    }
+
+    let newest = backup_path(path, 1);
+    if newest.exists() {
+        fs::remove_file(&newest)?;
+    }
+    // The vault itself is replaced by a rename, so a hard link keeps the old contents around
+    // without copying them.
+    if fs::hard_link(path, &newest).is_err() {
+        fs::copy(path, &newest)?;
+    }
+    Ok(())
+}
+
+/// Number of backups of `path` that exist, counting from the most recent one.
+pub fn count_backups(path: &Path) -> u32 {
+    (1..)
+        .take_while(|&index| backup_path(path, index).exists())
+        .count() as u32
+}
+
+/// Removes every backup of `path`.
+pub fn remove_backups(path: &Path) -> Result<()> {
+    for index in 1..=count_backups(path) {
+        fs::remove_file(backup_path(path, index))?;
+    }
+    Ok(())
+}
+
+/// Writes `path` through `write` without ever leaving it partially written. The contents go to a
+/// temporary file in the same directory, which is synced to disk and then renamed over `path`.
+/// The previous contents are kept among `backups` backups, rotated only once the new contents
+/// are on disk, so that a failed write never costs a backup.
+pub fn write_atomically<F>(path: &Path, backups: u32, write: F) -> Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<()>,
+{
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let file_name = path
+        .file_name()
+        .ok_or(RustPassError::InvalidInput(String::from(
+            "Invalid vault path",
+        )))?
+        .to_string_lossy();
+    let tmp_path = dir.join(format!(".{}.{}.tmp", file_name, process::id()));
+
+    let result = (|| {
+        let mut writer = BufWriter::new(create_private(&tmp_path)?);
+        write(&mut writer)?;
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        rotate_backups(path, backups)?;
+        fs::rename(&tmp_path, path)?;
+        sync_dir(dir)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Creates a file only readable by the current user, replacing any existing one.
+fn create_private(path: &Path) -> Result<File> {
+    Ok(private_options().create(true).truncate(true).open(path)?)
+}
+
+/// Creates a file only readable by the current user, failing if it already exists.
+pub fn create_new_private(path: &Path) -> io::Result<File> {
+    private_options().create_new(true).open(path)
+}
+
+fn private_options() -> OpenOptions {
+    let mut options = OpenOptions::new();
+    options.write(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+}
+
+/// Makes the rename of a file inside `dir` durable.
+fn sync_dir(dir: &Path) -> Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+/// Removes a vault written by a test along with its backups.
+#[cfg(test)]
+pub fn remove_with_backups(path: &Path) {
+    fs::remove_file(path).unwrap();
+    for index in 1.. {
+        if fs::remove_file(backup_path(path, index)).is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use std::{env, io::Write};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rustpass-{}-{}", name, process::id()));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_failed_write_keeps_original() {
+        let dir = temp_dir("atomic");
+        let path = dir.join("vault.rpdb");
+        fs::write(&path, b"original").unwrap();
+        fs::write(backup_path(&path, 1), b"backup").unwrap();
+
+        let result = write_atomically(&path, 1, |writer| {
+            writer.write_all(b"partial")?;
+            Err(anyhow!("Disk full"))
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"original");
+        assert_eq!(fs::read(backup_path(&path, 1)).unwrap(), b"backup");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        write_atomically(&path, 1, |writer| Ok(writer.write_all(b"updated")?)).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"updated");
+        assert_eq!(fs::read(backup_path(&path, 1)).unwrap(), b"original");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotate_backups() {
+        let dir = temp_dir("backups");
+        let path = dir.join("vault.rpdb");
+        rotate_backups(&path, 2).unwrap();
+        assert!(!backup_path(&path, 1).exists());
+
+        for contents in ["v1", "v2", "v3", "v4"] {
+            write_atomically(
+                &path,
+                2,
+                |writer| Ok(writer.write_all(contents.as_bytes())?),
+            )
+            .unwrap();
+        }
+        assert_eq!(fs::read(&path).unwrap(), b"v4");
+        assert_eq!(fs::read(backup_path(&path, 1)).unwrap(), b"v3");
+        assert_eq!(fs::read(backup_path(&path, 2)).unwrap(), b"v2");
+        assert!(!backup_path(&path, 3).exists());
+
+        rotate_backups(&path, 0).unwrap();
+        assert_eq!(fs::read(backup_path(&path, 1)).unwrap(), b"v3");
+
+        assert_eq!(count_backups(&path), 2);
+        remove_backups(&path).unwrap();
+        assert_eq!(count_backups(&path), 0);
+        assert!(path.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
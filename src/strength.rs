@@ -0,0 +1,324 @@
+//! Password strength estimation, after zxcvbn. A password is split into the cheapest sequence of
+//! patterns an attacker would try, such as common passwords, dictionary words, keyboard walks,
+//! sequences, repeats and years, with brute force filling the gaps. The number of guesses is the
+//! product of the guesses for each part.
+
+/// Most common passwords, most common first
+const COMMON_PASSWORDS: &[&str] = &[
+    "123456",
+    "password",
+    "12345678",
+    "qwerty",
+    "123456789",
+    "12345",
+    "1234",
+    "111111",
+    "1234567",
+    "dragon",
+    "123123",
+    "baseball",
+    "abc123",
+    "football",
+    "monkey",
+    "letmein",
+    "696969",
+    "shadow",
+    "master",
+    "666666",
+    "qwertyuiop",
+    "123321",
+    "mustang",
+    "1234567890",
+    "michael",
+    "654321",
+    "superman",
+    "1qaz2wsx",
+    "7777777",
+    "121212",
+    "000000",
+    "qazwsx",
+    "123qwe",
+    "killer",
+    "trustno1",
+    "jordan",
+    "jennifer",
+    "zxcvbnm",
+    "asdfgh",
+    "hunter",
+    "buster",
+    "soccer",
+    "harley",
+    "batman",
+    "andrew",
+    "tigger",
+    "sunshine",
+    "iloveyou",
+    "2000",
+    "charlie",
+    "robert",
+    "thomas",
+    "hockey",
+    "ranger",
+    "daniel",
+    "starwars",
+    "klaster",
+    "112233",
+    "george",
+    "computer",
+    "michelle",
+    "jessica",
+    "pepper",
+    "1111",
+    "zxcvbn",
+    "555555",
+    "11111111",
+    "131313",
+    "freedom",
+    "777777",
+    "pass",
+    "maggie",
+    "159753",
+    "aaaaaa",
+    "ginger",
+    "princess",
+    "joshua",
+    "cheese",
+    "amanda",
+    "summer",
+    "love",
+    "ashley",
+    "nicole",
+    "chelsea",
+    "biteme",
+    "matthew",
+    "access",
+    "yankees",
+    "987654321",
+    "dallas",
+    "austin",
+    "thunder",
+    "taylor",
+    "matrix",
+    "admin",
+    "welcome",
+    "login",
+    "secret",
+    "passw0rd",
+    "hello",
+    "changeme",
+    "qwerty123",
+    "password1",
+    "abcdef",
+    "abcd1234",
+    "default",
+    "guest",
+    "root",
+    "test",
+];
+
+/// Words of the bundled passphrase wordlist, after their dice rolls
+const WORDLIST: &str = include_str!("wordlist.txt");
+
+/// Length of the longest common password or word, beyond which no token is looked up
+const LONGEST_WORD: usize = 10;
+
+const KEYBOARD_ROWS: &[&str] = &["1234567890", "qwertyuiop", "asdfghjkl", "zxcvbnm"];
+
+/// Guesses below which a password gets each score, as in zxcvbn
+const SCORE_THRESHOLDS: [f64; 4] = [1e3, 1e6, 1e8, 1e10];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Strength {
+    /// Base 10 logarithm of the estimated number of guesses
+    pub log_guesses: f64,
+    /// From 0, guessed almost instantly, to 4, safe against offline attacks
+    pub score: u8,
+}
+
+impl Strength {
+    pub fn is_weak(&self) -> bool {
+        self.score < 3
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self.score {
+            0 => "very weak",
+            1 => "weak",
+            2 => "fair",
+            3 => "strong",
+            _ => "very strong",
+        }
+    }
+}
+
+/// Estimates how many guesses it takes to find `password`.
+pub fn estimate(password: &str) -> Strength {
+    let chars: Vec<char> = password.chars().collect();
+    // best[j] is the cheapest way to guess the first j characters, in log10 guesses.
+    let mut best = vec![f64::INFINITY; chars.len() + 1];
+    best[0] = 0.0;
+    for end in 1..=chars.len() {
+        best[end] = best[end - 1] + (brute_force_cardinality(chars[end - 1]) as f64).log10();
+        for start in 0..end {
+            if let Some(guesses) = pattern_guesses(&chars[start..end]) {
+                best[end] = best[end].min(best[start] + guesses.log10());
+            }
+        }
+    }
+    let log_guesses = best[chars.len()];
+    let score = SCORE_THRESHOLDS
+        .iter()
+        .take_while(|threshold| log_guesses >= threshold.log10())
+        .count() as u8;
+    Strength { log_guesses, score }
+}
+
+/// Guesses for `token` if it matches a pattern, taking the cheapest one.
+fn pattern_guesses(token: &[char]) -> Option<f64> {
+    [
+        dictionary_guesses(token),
+        repeat_guesses(token),
+        sequence_guesses(token),
+        keyboard_guesses(token),
+        year_guesses(token),
+    ]
+    .into_iter()
+    .flatten()
+    .reduce(f64::min)
+}
+
+fn brute_force_cardinality(c: char) -> u32 {
+    match c {
+        '0'..='9' => 10,
+        'a'..='z' | 'A'..='Z' => 26,
+        c if c.is_ascii() => 33,
+        _ => 100,
+    }
+}
+
+/// Undoes the usual l33t substitutions.
+fn unleet(c: char) -> char {
+    match c {
+        '0' => 'o',
+        '1' | '!' => 'i',
+        '3' => 'e',
+        '4' | '@' => 'a',
+        '5' | '$' => 's',
+        '7' => 't',
+        c => c,
+    }
+}
+
+/// Common passwords are guessed in order, then words of the wordlist. Capitals and l33t
+/// substitutions double the guesses each, or more when they are not just the first letter.
+fn dictionary_guesses(token: &[char]) -> Option<f64> {
+    if token.len() < 3 || token.len() > LONGEST_WORD {
+        return None;
+    }
+    let lower: String = token.iter().flat_map(|c| c.to_lowercase()).collect();
+    let plain: String = lower.chars().map(unleet).collect();
+    let rank = |word: &str| {
+        COMMON_PASSWORDS
+            .iter()
+            .position(|common| *common == word)
+            .map(|rank| rank + 1)
+            .or_else(|| {
+                WORDLIST
+                    .lines()
+                    .filter_map(|line| line.split_whitespace().last())
+                    .position(|w| w == word)
+                    .map(|rank| COMMON_PASSWORDS.len() + rank + 1)
+            })
+    };
+    let (rank, leet) = match rank(&lower) {
+        Some(rank) => (rank, false),
+        None => (rank(&plain)?, true),
+    };
+
+    let mut guesses = rank as f64;
+    let uppercase = token.iter().filter(|c| c.is_uppercase()).count();
+    if uppercase > 0 {
+        let only_first = uppercase == 1 && token[0].is_uppercase();
+        guesses *= if only_first {
+            2.0
+        } else {
+            2f64.powi(uppercase as i32)
+        };
+    }
+    if leet {
+        guesses *= 2.0;
+    }
+    Some(guesses)
+}
+
+/// The same character over and over.
+fn repeat_guesses(token: &[char]) -> Option<f64> {
+    if token.len() < 3 || token.iter().any(|&c| c != token[0]) {
+        return None;
+    }
+    Some(brute_force_cardinality(token[0]) as f64 * token.len() as f64)
+}
+
+/// Characters whose code points go up or down by one, such as `abcd` or `9876`.
+fn sequence_guesses(token: &[char]) -> Option<f64> {
+    if token.len() < 3 {
+        return None;
+    }
+    let delta = token[1] as i64 - token[0] as i64;
+    if delta.abs() != 1 || token.windows(2).any(|w| w[1] as i64 - w[0] as i64 != delta) {
+        return None;
+    }
+    let start = match token[0] {
+        'a' | 'A' | 'z' | 'Z' | '0' | '1' | '9' => 4.0,
+        c if c.is_ascii_digit() => 10.0,
+        _ => 26.0,
+    };
+    let direction = if delta < 0 { 2.0 } else { 1.0 };
+    Some(start * direction * token.len() as f64)
+}
+
+/// Runs along a row of the keyboard, in either direction.
+fn keyboard_guesses(token: &[char]) -> Option<f64> {
+    if token.len() < 3 {
+        return None;
+    }
+    let lower: String = token.iter().flat_map(|c| c.to_lowercase()).collect();
+    let reversed: String = lower.chars().rev().collect();
+    let direction = if KEYBOARD_ROWS.iter().any(|row| row.contains(&lower)) {
+        1.0
+    } else if KEYBOARD_ROWS.iter().any(|row| row.contains(&reversed)) {
+        2.0
+    } else {
+        return None;
+    };
+    Some(KEYBOARD_ROWS.len() as f64 * 10.0 * direction * token.len() as f64)
+}
+
+/// Years between 1900 and 2099.
+fn year_guesses(token: &[char]) -> Option<f64> {
+    let year: String = token.iter().collect();
+    match year.parse::<u32>() {
+        Ok(1900..=2099) if token.len() == 4 => Some(200.0),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patterns() {
+        assert_eq!(estimate("").score, 0);
+        assert_eq!(estimate("password").score, 0);
+        assert_eq!(estimate("P@ssw0rd").score, 0);
+        assert_eq!(estimate("qwertyuiop").score, 0);
+        assert_eq!(estimate("aaaaaaaaaaaa").score, 0);
+        assert_eq!(estimate("abcdefgh").score, 0);
+        assert!(estimate("Password1999").is_weak());
+        assert!(estimate("zebra-tulip").is_weak());
+        assert!(!estimate("zebra-tulip-violin-quilt").is_weak());
+        assert!(!estimate("Xk9#mQ2!vL7p").is_weak());
+        assert!(estimate("kx9mq2vl").log_guesses > estimate("password1").log_guesses);
+    }
+}